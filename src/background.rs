@@ -0,0 +1,116 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::str;
+
+/// File of a background process in `.minitel/run`, such as `backend.tunnel.pid`.
+pub fn get_run_file(project_location: &str, name: &str, kind: &str, extension: &str) -> PathBuf {
+    Path::new(project_location).join(".minitel/run").join(format!("{}.{}.{}", name, kind, extension))
+}
+
+/// True if the process was started and not stopped since.
+pub fn is_running(project_location: &str, name: &str, kind: &str) -> bool {
+    get_run_file(project_location, name, kind, "pid").is_file()
+}
+
+/// Runs the shell `script` in the background from the project directory, with `args` as `$0`,
+/// `$1`..., replacing the process of the same name and kind. Its output goes to `log`, or is dropped.
+///
+/// The process outlives minitel, until `stop` kills it. Returns false if it could not be started.
+pub fn spawn<I, S>(project_location: &str, name: &str, kind: &str, script: &str, args: I, log: Option<&Path>) -> bool
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>
+{
+    stop(project_location, name, kind);
+
+    let pid_file = get_run_file(project_location, name, kind, "pid");
+    fs::create_dir_all(pid_file.parent().unwrap()).expect("Could not create run directory");
+    let output = match log {
+        Some(log) => Stdio::from(File::create(log).unwrap_or_else(|e| panic!("Could not create {}: {}", log.display(), e))),
+        None => Stdio::null()
+    };
+
+    // The pid comes back on stderr, stdout belongs to the process
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} 2>&1 < /dev/null & echo $! >&2", script))
+        .args(args)
+        .current_dir(project_location)
+        .stdout(output)
+        .output()
+        .expect("Failed to execute command");
+
+    let pid = str::from_utf8(result.stderr.as_slice()).expect("");
+
+    if !result.status.success() || pid.trim().is_empty() {
+        return false;
+    }
+
+    fs::write(&pid_file, pid.trim()).expect("Could not write pid file");
+    true
+}
+
+pub fn stop(project_location: &str, name: &str, kind: &str) {
+    let pid_file = get_run_file(project_location, name, kind, "pid");
+
+    if let Ok(pid) = fs::read_to_string(&pid_file) {
+        let _ = Command::new("kill").arg(pid.trim()).status();
+        let _ = fs::remove_file(&pid_file);
+    }
+}
+
+/// Stops every process of a kind, such as all the tunnels.
+pub fn stop_all(project_location: &str, kind: &str) {
+    let suffix = format!(".{}.pid", kind);
+
+    if let Ok(entries) = fs::read_dir(Path::new(project_location).join(".minitel/run")) {
+        for entry in entries.map_while(Result::ok) {
+            let file_name = entry.file_name();
+            if let Some(name) = file_name.to_str().and_then(|f| f.strip_suffix(&suffix)) {
+                stop(project_location, name, kind);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Killed orphans stay zombies until init reaps them, which some containers never do.
+    fn is_alive(pid: &str) -> bool {
+        let output = Command::new("ps").arg("-o").arg("stat=").arg("-p").arg(pid.trim()).output().unwrap();
+        let state = String::from_utf8(output.stdout).unwrap();
+        !state.trim().is_empty() && !state.trim().starts_with('Z')
+    }
+
+    #[test]
+    fn spawn_keeps_the_pid_and_output_until_stopped() {
+        let project = env::temp_dir().join(format!("minitel-background-{}", process::id()));
+        let location = project.to_str().unwrap();
+        let log = get_run_file(location, "greeter", "test", "log");
+
+        assert!(spawn(location, "greeter", "test", "echo \"$0 $1\"; exec sleep 30", ["hello", "world"], Some(&log)));
+        assert!(is_running(location, "greeter", "test"));
+        let pid = fs::read_to_string(get_run_file(location, "greeter", "test", "pid")).unwrap();
+        assert!(is_alive(&pid));
+
+        stop_all(location, "test");
+        assert!(!is_running(location, "greeter", "test"));
+        // kill only sends the signal, the process may take a moment to go
+        thread::sleep(Duration::from_millis(200));
+        assert!(!is_alive(&pid));
+        assert_eq!(fs::read_to_string(&log).unwrap(), "hello world\n");
+
+        fs::remove_dir_all(project).unwrap();
+    }
+}
@@ -0,0 +1,40 @@
+use crate::Chart;
+
+use std::path::Path;
+use std::process::Command;
+use std::str;
+
+fn run_renderer(command: &mut Command, chart_name: &str) -> String {
+    let output = command.output()
+        .unwrap_or_else(|e| panic!("Failed to render chart {}: {}", chart_name, e));
+
+    if !output.status.success() {
+        panic!("Failed to render chart {}: {}", chart_name, str::from_utf8(&output.stderr).unwrap().trim());
+    }
+
+    String::from(str::from_utf8(&output.stdout).unwrap())
+}
+
+/// Renders the manifests of a chart with `helm template` or `kustomize build`.
+pub fn render_chart(project_location: &str, namespace: &str, chart: &Chart) -> String {
+    let project = Path::new(project_location);
+
+    match (&chart.helm, &chart.kustomize) {
+        (Some(helm), None) => {
+            let mut command = Command::new("helm");
+            command.arg("template")
+                .arg(&chart.name)
+                .arg(project.join(helm))
+                .arg("--namespace")
+                .arg(namespace);
+            for values in &chart.values {
+                command.arg("--values").arg(project.join(values));
+            }
+            run_renderer(&mut command, &chart.name)
+        },
+        (None, Some(kustomize)) => {
+            run_renderer(Command::new("kustomize").arg("build").arg(project.join(kustomize)), &chart.name)
+        },
+        _ => panic!("Chart {} must set exactly one of helm or kustomize", chart.name)
+    }
+}
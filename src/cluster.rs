@@ -0,0 +1,628 @@
+use crate::k8s::KubeTarget;
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use kube::Client;
+use kube::Config;
+use kube::api::Api;
+use kube::api::DeleteParams;
+use kube::api::DynamicObject;
+use kube::api::ListParams;
+use kube::api::Patch;
+use kube::api::PatchParams;
+use kube::config::KubeConfigOptions;
+use kube::config::Kubeconfig;
+use kube::core::GroupVersionKind;
+use kube::discovery::verbs;
+use kube::discovery::ApiCapabilities;
+use kube::discovery::ApiResource;
+use kube::discovery::Discovery;
+use kube::discovery::Scope;
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::batch::v1::Job;
+use k8s_openapi::api::core::v1::Pod;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::runtime::Runtime;
+
+/// Field manager used for server-side apply
+pub const FIELD_MANAGER: &str = "minitel";
+
+/// How long a rollout may take, on top of the progress deadline of the Deployment.
+const ROLLOUT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Identifies an object of the cluster, within the namespace of the app for namespaced kinds.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectRef {
+    pub api_version: String,
+    pub kind: String,
+    pub name: String,
+    /// Set for objects of another namespace, which extra manifests and charts may name explicitly
+    pub namespace: Option<String>
+}
+
+impl ObjectRef {
+    fn group(&self) -> &str {
+        self.api_version.rsplit_once('/').map_or("", |(group, _)| group)
+    }
+
+    /// True if both refer to the same object, possibly through different versions of its API.
+    pub fn is_same_object(&self, other: &ObjectRef) -> bool {
+        self.group() == other.group() && self.kind == other.kind && self.name == other.name && self.namespace == other.namespace
+    }
+
+    /// Leaves out the namespace when it is the one of the app.
+    pub fn relative_to(mut self, namespace: &str) -> ObjectRef {
+        if self.namespace.as_deref() == Some(namespace) {
+            self.namespace = None;
+        }
+        self
+    }
+}
+
+impl fmt::Display for ObjectRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.kind.to_lowercase(), self.name)?;
+        match &self.namespace {
+            Some(namespace) => write!(f, " in namespace {}", namespace),
+            None => Ok(())
+        }
+    }
+}
+
+/// Phase of a pod, with the reason its containers wait to start, if they do.
+#[derive(Debug, PartialEq)]
+pub struct PodState {
+    pub phase: String,
+    pub waiting_reason: Option<String>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed
+}
+
+/// Everything minitel needs from the cluster.
+///
+/// Implementations panic on unexpected API errors, like the rest of minitel does on failed commands.
+pub trait ClusterApi {
+    /// Server-side applies every document of a YAML stream and returns the applied objects.
+    fn apply(&self, manifests: &str) -> Vec<ObjectRef>;
+
+    /// Returns the live state of an object, if it exists.
+    fn get(&self, object: &ObjectRef) -> Option<serde_json::Value>;
+
+    /// Lists the objects of any kind, in any namespace or cluster-scoped, matching a label selector.
+    ///
+    /// Objects owned by another one (pods of a job, endpoint slices...) are left out,
+    /// Kubernetes manages them along with their owner.
+    fn list(&self, selector: &str) -> Vec<ObjectRef>;
+
+    fn delete(&self, object: &ObjectRef);
+
+    /// Blocks until every replica of a deployment runs its latest revision. Panics, like
+    /// `kubectl rollout status`, when the rollout exceeds its progress deadline.
+    fn wait_for_rollout(&self, deployment: &str);
+
+    /// Replaces the pods of a deployment, like `kubectl rollout restart`.
+    fn restart_rollout(&self, deployment: &str);
+
+    /// Returns the state of the first pod matching a label selector which isn't being deleted.
+    fn pod_state(&self, selector: &str) -> Option<PodState>;
+
+    /// Returns the name of a running pod matching a label selector.
+    fn find_running_pod(&self, selector: &str) -> Option<String>;
+
+    /// Returns the name of a ready pod matching a label selector.
+    fn find_ready_pod(&self, selector: &str) -> Option<String>;
+
+    fn job_status(&self, name: &str) -> JobStatus;
+}
+
+/// Splits a YAML stream into its objects.
+pub fn parse_manifests(manifests: &str) -> Vec<(ObjectRef, serde_json::Value)> {
+    let mut objects = Vec::new();
+
+    for document in serde_yaml::Deserializer::from_str(manifests) {
+        let value = serde_yaml::Value::deserialize(document).expect("Invalid manifest");
+        if value.is_null() {
+            continue;
+        }
+        let value = serde_json::to_value(value).expect("Invalid manifest");
+        let field = |pointer: &str| String::from(value.pointer(pointer).and_then(|v| v.as_str()).unwrap_or_default());
+
+        objects.push((ObjectRef {
+            api_version: field("/apiVersion"),
+            kind: field("/kind"),
+            name: field("/metadata/name"),
+            namespace: value.pointer("/metadata/namespace").and_then(|v| v.as_str()).map(String::from)
+        }, value));
+    }
+
+    objects
+}
+
+/// Names of the contexts defined in the kubeconfig.
+pub fn list_contexts() -> Vec<String> {
+    Kubeconfig::read()
+        .map(|config| config.contexts.into_iter().map(|c| c.name).collect())
+        .unwrap_or_default()
+}
+
+/// `ClusterApi` talking to the API server of the target context.
+pub struct KubeClient {
+    runtime: Runtime,
+    client: Client,
+    namespace: String,
+    discovery: RefCell<Option<Rc<Discovery>>>
+}
+
+impl KubeClient {
+    pub fn new(target: &KubeTarget) -> KubeClient {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to start async runtime");
+
+        let options = KubeConfigOptions {
+            context: Some(target.context.clone()),
+            ..KubeConfigOptions::default()
+        };
+        let client = runtime.block_on(async {
+            let config = Config::from_kubeconfig(&options).await
+                .unwrap_or_else(|e| panic!("Could not load kubeconfig for context {}: {}", target.context, e));
+            Client::try_from(config).expect("Could not create kube client")
+        });
+
+        KubeClient {
+            runtime,
+            client,
+            namespace: target.namespace.clone(),
+            discovery: RefCell::new(None)
+        }
+    }
+
+    fn discovery(&self) -> Rc<Discovery> {
+        if let Some(discovery) = self.discovery.borrow().as_ref() {
+            return discovery.clone();
+        }
+
+        let discovery = Rc::new(self.runtime.block_on(Discovery::new(self.client.clone()).run())
+            .expect("Could not discover the cluster's API resources"));
+        self.discovery.replace(Some(discovery.clone()));
+        discovery
+    }
+
+    fn resolve(&self, api_version: &str, kind: &str) -> (ApiResource, ApiCapabilities) {
+        let (group, version) = match api_version.split_once('/') {
+            Some((group, version)) => (group, version),
+            None => ("", api_version)
+        };
+
+        let gvk = GroupVersionKind::gvk(group, version, kind);
+
+        if let Some(found) = self.discovery().resolve_gvk(&gvk) {
+            return found;
+        }
+
+        // The kind may come from a CRD applied after the last discovery
+        self.discovery.replace(None);
+        self.discovery().resolve_gvk(&gvk)
+            .unwrap_or_else(|| panic!("Unknown resource kind {} in {}", kind, api_version))
+    }
+
+    fn dynamic_api(&self, api_version: &str, kind: &str, namespace: Option<&str>) -> Api<DynamicObject> {
+        let (resource, capabilities) = self.resolve(api_version, kind);
+
+        match capabilities.scope {
+            Scope::Namespaced => Api::namespaced_with(self.client.clone(), namespace.unwrap_or(&self.namespace), &resource),
+            Scope::Cluster => Api::all_with(self.client.clone(), &resource)
+        }
+    }
+
+    fn api<K>(&self) -> Api<K>
+        where K: kube::Resource<Scope = k8s_openapi::NamespaceResourceScope>, <K as kube::Resource>::DynamicType: Default {
+        Api::namespaced(self.client.clone(), &self.namespace)
+    }
+}
+
+impl ClusterApi for KubeClient {
+    fn apply(&self, manifests: &str) -> Vec<ObjectRef> {
+        let mut applied = Vec::new();
+
+        for document in serde_yaml::Deserializer::from_str(manifests) {
+            let value = serde_yaml::Value::deserialize(document).expect("Invalid manifest");
+            if value.is_null() {
+                continue;
+            }
+            let object: DynamicObject = serde_yaml::from_value(value).expect("Invalid manifest");
+            let types = object.types.clone().expect("Manifest without apiVersion or kind");
+            let name = object.metadata.name.clone().expect("Manifest without metadata.name");
+
+            let namespace = object.metadata.namespace.clone();
+            let api = self.dynamic_api(&types.api_version, &types.kind, namespace.as_deref());
+            let params = PatchParams::apply(FIELD_MANAGER).force();
+
+            self.runtime.block_on(api.patch(&name, &params, &Patch::Apply(&object)))
+                .unwrap_or_else(|e| panic!("Failed to apply {}/{}: {}", types.kind, name, e));
+            println!("{}/{} applied", types.kind.to_lowercase(), name);
+
+            let (_, capabilities) = self.resolve(&types.api_version, &types.kind);
+            applied.push(ObjectRef {
+                api_version: types.api_version,
+                kind: types.kind,
+                name,
+                namespace: namespace.filter(|_| capabilities.scope == Scope::Namespaced)
+            }.relative_to(&self.namespace));
+        }
+
+        applied
+    }
+
+    fn get(&self, object: &ObjectRef) -> Option<serde_json::Value> {
+        let api = self.dynamic_api(&object.api_version, &object.kind, object.namespace.as_deref());
+
+        self.runtime.block_on(api.get_opt(&object.name))
+            .unwrap_or_else(|e| panic!("Failed to get {}: {}", object, e))
+            .map(|o| serde_json::to_value(o).unwrap())
+    }
+
+    fn list(&self, selector: &str) -> Vec<ObjectRef> {
+        let params = ListParams::default().labels(selector);
+        let mut objects = Vec::new();
+
+        let discovery = self.discovery();
+
+        for group in discovery.groups() {
+            for (resource, capabilities) in group.recommended_resources() {
+                if !capabilities.supports_operation(verbs::LIST) {
+                    continue;
+                }
+
+                // Extra manifests and charts may create objects in other namespaces
+                let all: Api<DynamicObject> = Api::all_with(self.client.clone(), &resource);
+                let list = match self.runtime.block_on(all.list_metadata(&params)) {
+                    Ok(list) => Ok(list),
+                    // Users who may only read the namespace of the app still see their objects there
+                    Err(_) if capabilities.scope == Scope::Namespaced => {
+                        let api: Api<DynamicObject> = Api::namespaced_with(self.client.clone(), &self.namespace, &resource);
+                        self.runtime.block_on(api.list_metadata(&params))
+                    },
+                    Err(e) => Err(e)
+                };
+                // Some aggregated APIs (metrics...) can't be listed with a selector, they hold nothing of ours anyway
+                if let Ok(list) = list {
+                    for item in list.items {
+                        if item.metadata.owner_references.is_some_and(|o| !o.is_empty()) {
+                            continue;
+                        }
+                        objects.push(ObjectRef {
+                            api_version: resource.api_version.clone(),
+                            kind: resource.kind.clone(),
+                            name: item.metadata.name.unwrap_or_default(),
+                            namespace: item.metadata.namespace
+                        }.relative_to(&self.namespace));
+                    }
+                }
+            }
+        }
+
+        objects.sort();
+        objects
+    }
+
+    fn delete(&self, object: &ObjectRef) {
+        let api = self.dynamic_api(&object.api_version, &object.kind, object.namespace.as_deref());
+
+        match self.runtime.block_on(api.delete(&object.name, &DeleteParams::background())) {
+            Ok(_) => println!("{} deleted", object),
+            Err(kube::Error::Api(e)) if e.code == 404 => {},
+            Err(e) => panic!("Failed to delete {}: {}", object, e)
+        }
+    }
+
+    fn wait_for_rollout(&self, deployment: &str) {
+        let api: Api<Deployment> = self.api();
+        let started = Instant::now();
+
+        loop {
+            let current = self.runtime.block_on(api.get(deployment))
+                .unwrap_or_else(|e| panic!("Failed to get deployment {}: {}", deployment, e));
+
+            let desired = current.spec.as_ref().and_then(|s| s.replicas).unwrap_or(1);
+            let generation = current.metadata.generation.unwrap_or(0);
+
+            if let Some(status) = current.status {
+                let done = status.observed_generation.unwrap_or(0) >= generation
+                    && status.updated_replicas.unwrap_or(0) == desired
+                    && status.replicas.unwrap_or(0) == desired
+                    && status.available_replicas.unwrap_or(0) == desired;
+                if done {
+                    println!("deployment/{} rolled out", deployment);
+                    return;
+                }
+
+                // Set when the pods can't start, such as with a missing image or a crash loop
+                let stalled = status.conditions.iter().flatten()
+                    .find(|c| c.type_ == "Progressing" && c.reason.as_deref() == Some("ProgressDeadlineExceeded"));
+                if let Some(condition) = stalled {
+                    panic!("Rollout of deployment/{} failed: {}", deployment, condition.message.as_deref().unwrap_or("progress deadline exceeded"));
+                }
+            }
+
+            if started.elapsed() > ROLLOUT_TIMEOUT {
+                panic!("Rollout of deployment/{} did not finish within {} seconds", deployment, ROLLOUT_TIMEOUT.as_secs());
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    fn restart_rollout(&self, deployment: &str) {
+        let api: Api<Deployment> = self.api();
+        let restarted_at = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+        let patch = json!({
+            "spec": { "template": { "metadata": { "annotations": {
+                "kubectl.kubernetes.io/restartedAt": restarted_at.to_string()
+            }}}}
+        });
+
+        self.runtime.block_on(api.patch(deployment, &PatchParams::default(), &Patch::Merge(&patch)))
+            .unwrap_or_else(|e| panic!("Failed to restart deployment {}: {}", deployment, e));
+    }
+
+    fn pod_state(&self, selector: &str) -> Option<PodState> {
+        let api: Api<Pod> = self.api();
+
+        let status = self.runtime.block_on(api.list(&ListParams::default().labels(selector)))
+            .unwrap_or_else(|e| panic!("Failed to list pods: {}", e))
+            .items.into_iter()
+            .find(|pod| pod.metadata.deletion_timestamp.is_none())?
+            .status
+            .unwrap_or_default();
+
+        let waiting_reason = status.init_container_statuses.iter().flatten()
+            .chain(status.container_statuses.iter().flatten())
+            .find_map(|container| container.state.as_ref()?.waiting.as_ref()?.reason.clone());
+
+        Some(PodState {
+            phase: status.phase.unwrap_or_else(|| String::from("Pending")),
+            waiting_reason
+        })
+    }
+
+    fn find_running_pod(&self, selector: &str) -> Option<String> {
+        let api: Api<Pod> = self.api();
+        let params = ListParams::default().labels(selector).fields("status.phase=Running");
+
+        self.runtime.block_on(api.list(&params))
+            .unwrap_or_else(|e| panic!("Failed to list pods: {}", e))
+            .items.into_iter()
+            .find(|pod| pod.metadata.deletion_timestamp.is_none())
+            .and_then(|pod| pod.metadata.name)
+    }
+
+    fn find_ready_pod(&self, selector: &str) -> Option<String> {
+        let api: Api<Pod> = self.api();
+        let params = ListParams::default().labels(selector).fields("status.phase=Running");
+
+        self.runtime.block_on(api.list(&params))
+            .unwrap_or_else(|e| panic!("Failed to list pods: {}", e))
+            .items.into_iter()
+            .filter(|pod| pod.metadata.deletion_timestamp.is_none())
+            .find(|pod| pod.status.as_ref()
+                .and_then(|status| status.conditions.as_ref())
+                .is_some_and(|conditions| conditions.iter().any(|c| c.type_ == "Ready" && c.status == "True")))
+            .and_then(|pod| pod.metadata.name)
+    }
+
+    fn job_status(&self, name: &str) -> JobStatus {
+        let api: Api<Job> = self.api();
+
+        let status = self.runtime.block_on(api.get(name))
+            .unwrap_or_else(|e| panic!("Failed to get job {}: {}", name, e))
+            .status
+            .unwrap_or_default();
+
+        if status.succeeded.unwrap_or(0) > 0 {
+            JobStatus::Succeeded
+        } else if status.failed.unwrap_or(0) > 0 {
+            JobStatus::Failed
+        } else {
+            JobStatus::Running
+        }
+    }
+}
+
+/// Wraps another `ClusterApi` and remembers every object applied through it.
+pub struct RecordingCluster<'a> {
+    inner: &'a dyn ClusterApi,
+    applied: RefCell<Vec<ObjectRef>>
+}
+
+impl<'a> RecordingCluster<'a> {
+    pub fn new(inner: &'a dyn ClusterApi) -> RecordingCluster<'a> {
+        RecordingCluster {
+            inner,
+            applied: RefCell::new(Vec::new())
+        }
+    }
+
+    pub fn applied(&self) -> Vec<ObjectRef> {
+        self.applied.borrow().clone()
+    }
+}
+
+impl ClusterApi for RecordingCluster<'_> {
+    fn apply(&self, manifests: &str) -> Vec<ObjectRef> {
+        let applied = self.inner.apply(manifests);
+        self.applied.borrow_mut().extend(applied.iter().cloned());
+        applied
+    }
+
+    fn get(&self, object: &ObjectRef) -> Option<serde_json::Value> {
+        self.inner.get(object)
+    }
+
+    fn list(&self, selector: &str) -> Vec<ObjectRef> {
+        self.inner.list(selector)
+    }
+
+    fn delete(&self, object: &ObjectRef) {
+        self.inner.delete(object)
+    }
+
+    fn wait_for_rollout(&self, deployment: &str) {
+        self.inner.wait_for_rollout(deployment)
+    }
+
+    fn restart_rollout(&self, deployment: &str) {
+        self.inner.restart_rollout(deployment)
+    }
+
+    fn pod_state(&self, selector: &str) -> Option<PodState> {
+        self.inner.pod_state(selector)
+    }
+
+    fn find_running_pod(&self, selector: &str) -> Option<String> {
+        self.inner.find_running_pod(selector)
+    }
+
+    fn find_ready_pod(&self, selector: &str) -> Option<String> {
+        self.inner.find_ready_pod(selector)
+    }
+
+    fn job_status(&self, name: &str) -> JobStatus {
+        self.inner.job_status(name)
+    }
+}
+
+/// Stand-in for the commands which only render manifests, such as `export`. Every call panics.
+pub struct OfflineCluster;
+
+impl OfflineCluster {
+    fn unavailable(&self) -> ! {
+        panic!("No cluster is available to this command");
+    }
+}
+
+impl ClusterApi for OfflineCluster {
+    fn apply(&self, _manifests: &str) -> Vec<ObjectRef> {
+        self.unavailable()
+    }
+
+    fn get(&self, _object: &ObjectRef) -> Option<serde_json::Value> {
+        self.unavailable()
+    }
+
+    fn list(&self, _selector: &str) -> Vec<ObjectRef> {
+        self.unavailable()
+    }
+
+    fn delete(&self, _object: &ObjectRef) {
+        self.unavailable()
+    }
+
+    fn wait_for_rollout(&self, _deployment: &str) {
+        self.unavailable()
+    }
+
+    fn restart_rollout(&self, _deployment: &str) {
+        self.unavailable()
+    }
+
+    fn pod_state(&self, _selector: &str) -> Option<PodState> {
+        self.unavailable()
+    }
+
+    fn find_running_pod(&self, _selector: &str) -> Option<String> {
+        self.unavailable()
+    }
+
+    fn find_ready_pod(&self, _selector: &str) -> Option<String> {
+        self.unavailable()
+    }
+
+    fn job_status(&self, _name: &str) -> JobStatus {
+        self.unavailable()
+    }
+}
+
+/// `ClusterApi` keeping the objects in memory, for tests. Its deployments and jobs are always
+/// done, and it has no pods.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryCluster {
+    objects: RefCell<std::collections::BTreeMap<ObjectRef, serde_json::Value>>
+}
+
+#[cfg(test)]
+impl MemoryCluster {
+    fn matches_selector(object: &serde_json::Value, selector: &str) -> bool {
+        selector.split(',').all(|requirement| match requirement.split_once('=') {
+            Some((key, value)) => object["metadata"]["labels"][key] == value,
+            None => false
+        })
+    }
+
+    pub fn contains(&self, kind: &str, name: &str) -> bool {
+        self.objects.borrow().keys().any(|o| o.kind == kind && o.name == name)
+    }
+}
+
+#[cfg(test)]
+impl ClusterApi for MemoryCluster {
+    fn apply(&self, manifests: &str) -> Vec<ObjectRef> {
+        parse_manifests(manifests).into_iter()
+            .map(|(object, value)| {
+                self.objects.borrow_mut().insert(object.clone(), value);
+                object
+            })
+            .collect()
+    }
+
+    fn get(&self, object: &ObjectRef) -> Option<serde_json::Value> {
+        self.objects.borrow().iter()
+            .find(|(o, _)| o.is_same_object(object))
+            .map(|(_, value)| value.clone())
+    }
+
+    fn list(&self, selector: &str) -> Vec<ObjectRef> {
+        self.objects.borrow().iter()
+            .filter(|(_, value)| value["metadata"]["ownerReferences"].as_array().is_none_or(|o| o.is_empty()))
+            .filter(|(_, value)| MemoryCluster::matches_selector(value, selector))
+            .map(|(object, _)| object.clone())
+            .collect()
+    }
+
+    fn delete(&self, object: &ObjectRef) {
+        self.objects.borrow_mut().retain(|o, _| !o.is_same_object(object));
+    }
+
+    fn wait_for_rollout(&self, _deployment: &str) {}
+
+    fn restart_rollout(&self, _deployment: &str) {}
+
+    fn pod_state(&self, _selector: &str) -> Option<PodState> {
+        None
+    }
+
+    fn find_running_pod(&self, _selector: &str) -> Option<String> {
+        None
+    }
+
+    fn find_ready_pod(&self, _selector: &str) -> Option<String> {
+        None
+    }
+
+    fn job_status(&self, _name: &str) -> JobStatus {
+        JobStatus::Succeeded
+    }
+}
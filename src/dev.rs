@@ -4,45 +4,141 @@ use crate::ServiceConfig;
 use crate::DeployMode;
 use std::collections::HashMap;
 use crate::Definition;
+use crate::Debugger;
+use crate::tunnel::find_tunnel_ports;
+use crate::proxy::find_proxy_port;
+use crate::discovery::ServiceAddress;
+use crate::discovery::build_discovery_env;
+use crate::discovery::list_named_ports;
+use crate::background::get_run_file;
+use std::fs;
 use std::process::Command;
+use std::process::ExitStatus;
 use std::path::PathBuf;
 use std::path::Path;
 
-pub fn build_development_env(def: &Definition, cfg: &HashMap<String, ServiceConfig>) -> HashMap<String, String> {
-
-    def.services.iter().map(|service| {
-        let name = format!("{}_SERVICE", service.name.to_uppercase());
-        match cfg.get(&service.name).map(|s| &s.deploy).get_or_insert(&DeployMode::Cluster) {
-            DeployMode::Cluster => {
-                let api_root = match &service.cluster_prefix {
-                    Some(e) => String::from(e),
-                    None => String::from("/")
-                };
-                (name, format!("http://{}{}", def.hostname, &api_root))
+/// Address at which local processes reach a service: cluster services are reached through their
+/// tunnel when they have one, through the ingress otherwise.
+pub fn create_local_address(project_location: &str, def: &Definition, cfg: &HashMap<String, ServiceConfig>, service: &Service) -> ServiceAddress {
+    let tunnel_ports = if service.tunnel { find_tunnel_ports(project_location, &service.name) } else { None };
+
+    match cfg.get(&service.name).map(|s| &s.deploy).get_or_insert(&DeployMode::Cluster) {
+        DeployMode::Cluster | DeployMode::Sync if tunnel_ports.is_some() => {
+            // The tunnel forwards the main port first, then the named ones in order
+            let ports = tunnel_ports.unwrap();
+            ServiceAddress {
+                host: String::from("localhost"),
+                port: i32::from(ports[0]),
+                path: String::new(),
+                named_ports: list_named_ports(service).into_iter()
+                    .zip(ports[1..].iter())
+                    .map(|((name, _), port)| (name, i32::from(*port)))
+                    .collect(),
+                secure: false
+            }
+        }
+        DeployMode::Cluster | DeployMode::Sync => {
+            let api_root = match &service.cluster_prefix {
+                Some(e) => String::from(e),
+                None => String::from("/")
+            };
+            // Only the main port is routed by the ingress
+            ServiceAddress {
+                host: def.hostname.clone(),
+                port: if def.tls { 443 } else { 80 },
+                path: api_root,
+                named_ports: Vec::new(),
+                secure: def.tls
             }
-            _ => {
-                (name, format!("http://localhost:{}", service.port))
+        }
+        _ => {
+            ServiceAddress {
+                host: String::from("localhost"),
+                port: service.port,
+                path: String::new(),
+                named_ports: list_named_ports(service),
+                secure: false
             }
         }
+    }
+}
+
+/// Addresses of the services for the local processes, through the recording proxy of those which have one.
+pub fn build_development_env(project_location: &str, def: &Definition, cfg: &HashMap<String, ServiceConfig>) -> HashMap<String, String> {
+
+    def.services.iter().flat_map(|service| {
+        let mut address = create_local_address(project_location, def, cfg, service);
+        if let Some(port) = find_proxy_port(project_location, &service.name) {
+            address = ServiceAddress {
+                host: String::from("localhost"),
+                port: i32::from(port),
+                path: String::new(),
+                secure: false,
+                ..address
+            };
+        }
+        build_discovery_env(def, &service.name, &address)
     }).collect()
 }
 
-pub fn start_development_service(project_location: &str, service: &Service, env: &HashMap<String, String>) {
+/// Host at which containers on the host reach the host itself.
+pub const CONTAINER_HOST_ALIAS: &str = "host.docker.internal";
+
+/// The development env as seen from a container on the host, where `localhost` is the container itself.
+///
+/// Tunnels only listen on the loopback interface of the host, which Docker Desktop forwards
+/// `host.docker.internal` to, but Docker on Linux does not.
+pub fn build_container_env(env: &HashMap<String, String>) -> HashMap<String, String> {
+    env.iter().map(|(name, value)| {
+        let value = if value == "localhost" {
+            String::from(CONTAINER_HOST_ALIAS)
+        } else {
+            value.replacen("://localhost", &format!("://{}", CONTAINER_HOST_ALIAS), 1)
+        };
+        (name.clone(), value)
+    }).collect()
+}
+
+/// Runs the `start.sh` of a service, with the variables and arguments enabling its debugger if `debug` is set.
+pub fn start_development_service(project_location: &str, service: &Service, env: &HashMap<String, String>, debug: Option<&Debugger>) {
     let script = get_script(project_location, &service.name, "start.sh");
-    run_script(&script, service.port, env);
+    let mut env = env.clone();
+    if let Some(debug) = debug {
+        env.extend(debug.env.clone());
+    }
+    run_script(&script, service.port, &env, debug.map_or(&[], |d| d.args.as_slice()));
+
+    let started = get_started_file(project_location, &service.name);
+    fs::create_dir_all(started.parent().unwrap()).expect("Could not create run directory");
+    fs::write(&started, "").expect("Could not write run file");
 }
 
+/// Runs the `stop.sh` of a service, if it has one.
 pub fn stop_development_service(project_location: &str, service: &Service, env: &HashMap<String, String>) {
     let script = get_script(project_location, &service.name, "stop.sh");
-    run_script(&script, service.port, env);
+    if script.is_file() {
+        run_script(&script, service.port, env, &[]);
+    }
+    let _ = fs::remove_file(get_started_file(project_location, &service.name));
+}
+
+/// True if the local process of a service may still run: minitel started it and did not stop it
+/// since, or its `start.sh` left a `.pid` file.
+pub fn is_development_service_started(project_location: &str, service: &Service) -> bool {
+    get_started_file(project_location, &service.name).is_file() || get_script(project_location, &service.name, ".pid").is_file()
+}
+
+fn get_started_file(project_location: &str, service_name: &str) -> PathBuf {
+    get_run_file(project_location, service_name, "local", "started")
 }
 
 fn get_script(project_location: &str, service_name: &str, script: &str) -> PathBuf {
     Path::new(project_location).join("services").join(service_name).join(script)
 }
 
-fn run_script(script: &Path, port: i32, env: &HashMap<String, String>) {
+fn run_script(script: &Path, port: i32, env: &HashMap<String, String>, args: &[String]) {
     let status = Command::new(script)
+        .args(args)
         .current_dir(script.parent().unwrap())
         .envs(env)
         .env("PORT", format!("{}", port))
@@ -54,4 +150,77 @@ fn run_script(script: &Path, port: i32, env: &HashMap<String, String>) {
     if !status.success() {
         println!("[WARNING] Failed to run script {}", script.to_str().unwrap());
     }
-}
\ No newline at end of file
+}
+
+/// Runs a command in the directory of a service, with the same environment as its scripts.
+///
+/// Returns true if the command succeeded.
+pub fn run_development_command(project_location: &str, service: &Service, command: &[String], env: &HashMap<String, String>) -> bool {
+    exec_development_command(project_location, service, command, env).success()
+}
+
+/// Same as `run_development_command`, returning the exit status of the command.
+pub fn exec_development_command(project_location: &str, service: &Service, command: &[String], env: &HashMap<String, String>) -> ExitStatus {
+    let (program, args) = command.split_first().expect("Empty command");
+
+    Command::new(program)
+        .args(args)
+        .current_dir(Path::new(project_location).join("services").join(&service.name))
+        .envs(env)
+        .env("PORT", format!("{}", service.port))
+        .spawn()
+        .expect("Failed to execute command")
+        .wait()
+        .expect("Command failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    fn create_service(name: &str) -> Service {
+        serde_yaml::from_str(&format!("name: {}\nport: 8000\n", name)).unwrap()
+    }
+
+    fn write_script(path: &Path, content: &str) {
+        fs::write(path, content).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn stop_development_service_skips_services_without_scripts() {
+        let project = env::temp_dir().join(format!("minitel-dev-cluster-{}", process::id()));
+        let location = project.to_str().unwrap();
+        let service = create_service("backend");
+        fs::create_dir_all(project.join("services/backend")).unwrap();
+
+        assert!(!is_development_service_started(location, &service));
+        stop_development_service(location, &service, &HashMap::new());
+
+        fs::remove_dir_all(project).unwrap();
+    }
+
+    #[test]
+    fn development_service_is_started_until_stopped() {
+        let project = env::temp_dir().join(format!("minitel-dev-local-{}", process::id()));
+        let location = project.to_str().unwrap();
+        let service = create_service("backend");
+        let directory = project.join("services/backend");
+        fs::create_dir_all(&directory).unwrap();
+        write_script(&directory.join("start.sh"), "#!/bin/sh\necho $PORT > port\n");
+        write_script(&directory.join("stop.sh"), "#!/bin/sh\nrm port\n");
+
+        start_development_service(location, &service, &HashMap::new(), None);
+        assert!(is_development_service_started(location, &service));
+        assert_eq!(fs::read_to_string(directory.join("port")).unwrap(), "8000\n");
+
+        stop_development_service(location, &service, &HashMap::new());
+        assert!(!is_development_service_started(location, &service));
+        assert!(!directory.join("port").exists());
+
+        fs::remove_dir_all(project).unwrap();
+    }
+}
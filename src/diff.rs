@@ -0,0 +1,154 @@
+use serde_json::Value;
+use crate::cluster::FIELD_MANAGER;
+
+/// Lists the differences between the desired state of an object and its live state.
+///
+/// Only the fields set in `desired` are compared, everything else has been defaulted or
+/// populated by the server. Fields minitel applied before and `desired` no longer sets are
+/// reported as removed, the next apply deletes them.
+pub fn diff_object(desired: &Value, live: &Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    diff_value("", desired, live, &mut changes);
+    if let Some(applied) = find_applied_fields(live) {
+        diff_removed("", applied, desired, live, &mut changes);
+    }
+    changes
+}
+
+/// Fields set by the last apply of minitel, from the managed fields of the live object.
+fn find_applied_fields(live: &Value) -> Option<&Value> {
+    live["metadata"]["managedFields"].as_array()?.iter()
+        .find(|m| m["manager"] == FIELD_MANAGER && m["operation"] == "Apply")
+        .map(|m| &m["fieldsV1"])
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        String::from(key)
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn diff_value(path: &str, desired: &Value, live: &Value, changes: &mut Vec<String>) {
+    match (desired, live) {
+        (Value::Object(desired), Value::Object(live)) => {
+            for (key, value) in desired {
+                let child = child_path(path, key);
+                match live.get(key) {
+                    Some(live_value) => diff_value(&child, value, live_value, changes),
+                    None => changes.push(format!("+ {}: {}", child, value))
+                }
+            }
+        },
+        (Value::Array(desired), Value::Array(live)) => {
+            for (i, value) in desired.iter().enumerate() {
+                let child = format!("{}[{}]", path, i);
+                match live.get(i) {
+                    Some(live_value) => diff_value(&child, value, live_value, changes),
+                    None => changes.push(format!("+ {}: {}", child, value))
+                }
+            }
+            for (i, value) in live.iter().enumerate().skip(desired.len()) {
+                changes.push(format!("- {}[{}]: {}", path, i, value));
+            }
+        },
+        _ if desired == live => {},
+        _ => changes.push(format!("~ {}: {} -> {}", path, live, desired))
+    }
+}
+
+/// Walks the applied fields (`f:name` for a field, `k:{...}` for a keyed element of a list) and
+/// reports those which are live but no longer desired. Elements of lists are compared by
+/// `diff_value` already.
+fn diff_removed(path: &str, applied: &Value, desired: &Value, live: &Value, changes: &mut Vec<String>) {
+    for (key, fields) in applied.as_object().into_iter().flatten() {
+        if let (Some(name), Value::Object(desired)) = (key.strip_prefix("f:"), desired) {
+            let child = child_path(path, name);
+            match (desired.get(name), live.get(name)) {
+                (Some(desired_value), Some(live_value)) => diff_removed(&child, fields, desired_value, live_value, changes),
+                (None, Some(live_value)) => changes.push(format!("- {}: {}", child, live_value)),
+                (_, None) => {}
+            }
+        } else if let (Some(element), Value::Array(desired), Value::Array(live)) = (key.strip_prefix("k:"), desired, live) {
+            let element: Value = match serde_json::from_str(element) {
+                Ok(element) => element,
+                Err(_) => continue
+            };
+            let matches = |item: &&Value| element.as_object().is_some_and(|e| e.iter().all(|(k, v)| item.get(k) == Some(v)));
+            if let (Some(i), Some(live_value)) = (desired.iter().position(|d| matches(&d)), live.iter().find(matches)) {
+                diff_removed(&format!("{}[{}]", path, i), fields, &desired[i], live_value, changes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn ignores_fields_only_set_by_the_server() {
+        let desired = json!({"metadata": {"name": "api"}, "spec": {"replicas": 1}});
+        let live = json!({"metadata": {"name": "api", "uid": "1234"}, "spec": {"replicas": 1, "revisionHistoryLimit": 10}, "status": {}});
+
+        assert!(diff_object(&desired, &live).is_empty());
+    }
+
+    #[test]
+    fn reports_changed_and_added_fields() {
+        let desired = json!({"spec": {"replicas": 2, "paused": true}});
+        let live = json!({"spec": {"replicas": 1}});
+
+        assert_eq!(diff_object(&desired, &live), vec!["+ spec.paused: true", "~ spec.replicas: 1 -> 2"]);
+    }
+
+    #[test]
+    fn compares_arrays_by_index() {
+        let desired = json!({"ports": [{"port": 80}, {"port": 8080}]});
+        let live = json!({"ports": [{"port": 80, "protocol": "TCP"}, {"port": 9090}, {"port": 443}]});
+
+        assert_eq!(diff_object(&desired, &live), vec!["~ ports[1].port: 9090 -> 8080", "- ports[2]: {\"port\":443}"]);
+    }
+
+    #[test]
+    fn reports_elements_added_to_arrays() {
+        let desired = json!({"args": ["serve", "--debug"]});
+        let live = json!({"args": ["serve"]});
+
+        assert_eq!(diff_object(&desired, &live), vec!["+ args[1]: \"--debug\""]);
+    }
+
+    #[test]
+    fn reports_applied_fields_no_longer_desired() {
+        let desired = json!({
+            "metadata": {"labels": {"app": "shop"}},
+            "spec": {"containers": [{"name": "api", "image": "api:2"}]}
+        });
+        let live = json!({
+            "metadata": {
+                "labels": {"app": "shop", "team": "checkout"},
+                "annotations": {"owner": "ops"},
+                "managedFields": [
+                    {"manager": "kubectl", "operation": "Update", "fieldsV1": {"f:metadata": {"f:annotations": {"f:owner": {}}}}},
+                    {"manager": "minitel", "operation": "Apply", "fieldsV1": {
+                        "f:metadata": {"f:labels": {"f:app": {}, "f:team": {}}},
+                        "f:spec": {"f:containers": {"k:{\"name\":\"api\"}": {".": {}, "f:image": {}, "f:name": {}, "f:command": {}}}}
+                    }}
+                ]
+            },
+            "spec": {"containers": [{"name": "api", "image": "api:2", "command": ["serve"], "imagePullPolicy": "Always"}]}
+        });
+
+        assert_eq!(diff_object(&desired, &live), vec!["- metadata.labels.team: \"checkout\"", "- spec.containers[0].command: [\"serve\"]"]);
+    }
+
+    #[test]
+    fn reports_type_changes() {
+        let desired = json!({"port": "http"});
+        let live = json!({"port": 80});
+
+        assert_eq!(diff_object(&desired, &live), vec!["~ port: 80 -> \"http\""]);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::Definition;
+use crate::Service;
+
+/// Name of the variables holding the address of a service, unless `env-name` says otherwise.
+const DEFAULT_ENV_NAME: &str = "{name}_SERVICE";
+
+/// Where the consumers of a service reach it.
+pub struct ServiceAddress {
+    pub host: String,
+    pub port: i32,
+    /// Path the service is served under, empty when it owns the whole host
+    pub path: String,
+    /// Additional ports, when the consumer can reach them
+    pub named_ports: Vec<(String, i32)>,
+    /// Reached over HTTPS
+    pub secure: bool
+}
+
+impl ServiceAddress {
+    pub fn url(&self) -> String {
+        let (scheme, default_port) = if self.secure { ("https", 443) } else { ("http", 80) };
+
+        if self.port == default_port {
+            format!("{}://{}{}", scheme, self.host, self.path)
+        } else {
+            format!("{}://{}:{}{}", scheme, self.host, self.port, self.path)
+        }
+    }
+}
+
+/// Named ports of a service, sorted by name.
+pub fn list_named_ports(service: &Service) -> Vec<(String, i32)> {
+    service.ports.iter().flatten()
+        .map(|(name, port)| (name.clone(), *port))
+        .collect()
+}
+
+/// Turns a name into a valid variable name, `user-api` into `USER_API`.
+fn sanitise_env_name(name: &str) -> String {
+    let sanitised: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+
+    if sanitised.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", sanitised)
+    } else {
+        sanitised
+    }
+}
+
+fn create_env_prefix(def: &Definition, service_name: &str) -> String {
+    let template = def.env_name.as_deref().unwrap_or(DEFAULT_ENV_NAME);
+    sanitise_env_name(&template.replace("{name}", service_name))
+}
+
+/// Variables describing the address of a service, the same for cluster and local consumers:
+/// `<PREFIX>` and `<PREFIX>_URL` hold the URL, `<PREFIX>_HOST` and `<PREFIX>_PORT` its parts,
+/// and `<PREFIX>_<NAME>_PORT` every named port.
+pub fn build_discovery_env(def: &Definition, service_name: &str, address: &ServiceAddress) -> Vec<(String, String)> {
+    let prefix = create_env_prefix(def, service_name);
+    let url = address.url();
+
+    let mut env = vec!(
+        (format!("{}_URL", prefix), url.clone()),
+        (format!("{}_HOST", prefix), address.host.clone()),
+        (format!("{}_PORT", prefix), address.port.to_string())
+    );
+    for (name, port) in &address.named_ports {
+        env.push((format!("{}_{}_PORT", prefix, sanitise_env_name(name)), port.to_string()));
+    }
+    env.push((prefix, url));
+
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_definition(env_name: Option<&str>) -> Definition {
+        let mut def: Definition = serde_yaml::from_str("
+            hostname: shop.test
+            app-name: shop
+            services: []
+        ").unwrap();
+        def.env_name = env_name.map(String::from);
+        def
+    }
+
+    fn create_address(port: i32, secure: bool) -> ServiceAddress {
+        ServiceAddress {
+            host: "user-api-service".to_string(),
+            port,
+            path: String::new(),
+            named_ports: vec!(("grpc-web".to_string(), 50051)),
+            secure
+        }
+    }
+
+    #[test]
+    fn sanitise_env_name_replaces_dashes_and_dots() {
+        assert_eq!(sanitise_env_name("user-api"), "USER_API");
+        assert_eq!(sanitise_env_name("auth.v2"), "AUTH_V2");
+    }
+
+    #[test]
+    fn sanitise_env_name_prefixes_leading_digits() {
+        assert_eq!(sanitise_env_name("3d-render"), "_3D_RENDER");
+    }
+
+    #[test]
+    fn discovery_env_describes_the_address() {
+        let env = build_discovery_env(&create_definition(None), "user-api", &create_address(80, false));
+
+        assert_eq!(env, vec!(
+            ("USER_API_SERVICE_URL".to_string(), "http://user-api-service".to_string()),
+            ("USER_API_SERVICE_HOST".to_string(), "user-api-service".to_string()),
+            ("USER_API_SERVICE_PORT".to_string(), "80".to_string()),
+            ("USER_API_SERVICE_GRPC_WEB_PORT".to_string(), "50051".to_string()),
+            ("USER_API_SERVICE".to_string(), "http://user-api-service".to_string())
+        ));
+    }
+
+    #[test]
+    fn discovery_env_uses_env_name() {
+        let env = build_discovery_env(&create_definition(Some("{name}-addr")), "2fa", &create_address(8443, true));
+
+        assert_eq!(env[0], ("_2FA_ADDR_URL".to_string(), "https://user-api-service:8443".to_string()));
+        assert_eq!(env[4], ("_2FA_ADDR".to_string(), "https://user-api-service:8443".to_string()));
+    }
+}
@@ -1,5 +1,11 @@
 
+use crate::k8s::create_image_name;
+
+use std::collections::HashMap;
 use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::str;
 
 pub fn build_service_image(project_location: &str, app_name: &str, service_name: &str) {
     let service_image_name = format!("{}-{}", app_name, service_name);
@@ -18,4 +24,122 @@ pub fn build_service_image(project_location: &str, app_name: &str, service_name:
     if !status.success() {
         println!("Failed to build docker image");
     }
-}
\ No newline at end of file
+}
+
+/// Everything `docker run` needs to run a service, or one of its jobs, on the host.
+pub struct ContainerConfig<'a> {
+    pub app_name: &'a str,
+    pub service_name: &'a str,
+    pub env: &'a HashMap<String, String>,
+    /// Names resolved by the container, such as the hostname of the app
+    pub hosts: Vec<(String, String)>,
+    pub command: Option<&'a Vec<String>>,
+    pub args: Option<&'a Vec<String>>
+}
+
+fn create_container_name(app_name: &str, service_name: &str) -> String {
+    format!("{}-{}", app_name, service_name)
+}
+
+/// Adds the environment, hosts, image and command of a container to a `docker run`.
+fn add_container_config(cmd: &mut Command, config: &ContainerConfig) {
+    for (name, value) in config.env {
+        cmd.arg("--env").arg(format!("{}={}", name, value));
+    }
+    for (host, ip) in &config.hosts {
+        cmd.arg("--add-host").arg(format!("{}:{}", host, ip));
+    }
+
+    let command = config.command.filter(|c| !c.is_empty());
+    if let Some(command) = command {
+        cmd.arg("--entrypoint").arg(&command[0]);
+    }
+    cmd.arg(create_image_name(config.app_name, config.service_name));
+    if let Some(command) = command {
+        cmd.args(&command[1..]);
+    }
+    cmd.args(config.args.into_iter().flatten());
+}
+
+/// Starts a service container in the background, replacing any previous one.
+///
+/// The port is published on the same port of the host, `mounts` are bind mounts or named volumes.
+pub fn run_service_container(config: &ContainerConfig, port: i32, mounts: &[(String, String)]) {
+    remove_service_container(config.app_name, config.service_name);
+
+    let mut cmd = Command::new("docker");
+    cmd.arg("run")
+        .arg("--detach")
+        .arg("--name").arg(create_container_name(config.app_name, config.service_name))
+        .arg("--label").arg(format!("minitel-app={}", config.app_name))
+        .arg("--publish").arg(format!("{}:{}", port, port))
+        .arg("--env").arg(format!("PORT={}", port));
+    for (source, target) in mounts {
+        cmd.arg("--volume").arg(format!("{}:{}", source, target));
+    }
+    add_container_config(&mut cmd, config);
+
+    let status = cmd.stdout(Stdio::null())
+        .status()
+        .expect("Failed to execute command");
+
+    if !status.success() {
+        panic!("Failed to start container for {}", config.service_name);
+    }
+}
+
+/// Runs a one-off command in the image of a service. Returns true if the command succeeded.
+pub fn run_job_container(config: &ContainerConfig) -> bool {
+    let mut cmd = Command::new("docker");
+    cmd.arg("run").arg("--rm");
+    add_container_config(&mut cmd, config);
+
+    cmd.status()
+        .expect("Failed to execute command")
+        .success()
+}
+
+/// Runs a command in the container of a service with the terminal attached, allocating a TTY when `tty` is set.
+pub fn exec_service_container(app_name: &str, service_name: &str, tty: bool, command: &[String]) -> ExitStatus {
+    let mut cmd = Command::new("docker");
+    cmd.arg("exec").arg("--interactive");
+    if tty {
+        cmd.arg("--tty");
+    }
+
+    cmd.arg(create_container_name(app_name, service_name))
+        .args(command)
+        .status()
+        .expect("Failed to execute command")
+}
+
+pub fn remove_service_container(app_name: &str, service_name: &str) {
+    let _ = Command::new("docker")
+        .arg("rm")
+        .arg("--force")
+        .arg(create_container_name(app_name, service_name))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Removes every container minitel started for the app.
+pub fn remove_app_containers(app_name: &str) {
+    let output = Command::new("docker")
+        .arg("ps")
+        .arg("--all")
+        .arg("--quiet")
+        .arg("--filter").arg(format!("label=minitel-app={}", app_name))
+        .output()
+        .expect("Failed to execute command");
+
+    let ids: Vec<&str> = str::from_utf8(&output.stdout).unwrap().split_whitespace().collect();
+    if !ids.is_empty() {
+        let _ = Command::new("docker")
+            .arg("rm")
+            .arg("--force")
+            .args(ids)
+            .stdout(Stdio::null())
+            .status();
+    }
+}
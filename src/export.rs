@@ -0,0 +1,233 @@
+use crate::cluster::parse_manifests;
+use crate::cluster::ObjectRef;
+
+use std::fs;
+use std::path::Path;
+use serde_json::json;
+use serde_json::Value;
+
+pub enum ExportFormat {
+    Yaml,
+    Helm,
+    Kustomize
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Option<ExportFormat> {
+        match format {
+            "yaml" => Some(ExportFormat::Yaml),
+            "helm" => Some(ExportFormat::Helm),
+            "kustomize" => Some(ExportFormat::Kustomize),
+            _ => None
+        }
+    }
+}
+
+/// What an exported bundle lets its users change.
+pub struct ExportConfig<'a> {
+    pub app_name: &'a str,
+    pub namespace: &'a str,
+    pub hostname: &'a str,
+    /// Image of each service deployed in the cluster, by service name
+    pub images: Vec<(String, String)>
+}
+
+fn write_file(path: &Path, content: &str) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap_or_else(|e| panic!("Could not create {}: {}", parent.display(), e));
+    }
+    fs::write(path, content).unwrap_or_else(|e| panic!("Could not write {}: {}", path.display(), e));
+}
+
+fn to_yaml(value: &Value) -> String {
+    serde_yaml::to_string(value).expect("Failed to serialize")
+}
+
+fn create_object_file_name(object: &ObjectRef) -> String {
+    format!("{}-{}.yaml", object.kind.to_lowercase(), object.name)
+}
+
+/// Objects of the bundle, the namespace left out: Helm and Kustomize pick it at install time.
+fn namespaced_objects(manifests: &[String]) -> Vec<(ObjectRef, Value)> {
+    manifests.iter()
+        .flat_map(|m| parse_manifests(m))
+        .filter(|(object, _)| object.kind != "Namespace")
+        .collect()
+}
+
+/// Writes every manifest into a single `<app>.yaml` file.
+pub fn write_yaml_bundle(dir: &Path, export: &ExportConfig, manifests: &[String]) {
+    let mut bundle = format!("# Apply with: kubectl apply --namespace {} -f {}.yaml\n", export.namespace, export.app_name);
+    for (_, value) in manifests.iter().flat_map(|m| parse_manifests(m)) {
+        bundle.push_str(&to_yaml(&value));
+    }
+
+    write_file(&dir.join(format!("{}.yaml", export.app_name)), &bundle);
+}
+
+/// Turns the strings of an object into Helm template text reading the images and hostname from
+/// the values. Only values are templated, never keys.
+fn template_helm_values(value: &mut Value, export: &ExportConfig) {
+    match value {
+        Value::String(s) => {
+            // Braces of the manifest itself must reach the cluster verbatim
+            let text = s.replace("{{", "{{ `{{` }}");
+            *s = match export.images.iter().find(|(_, image)| *image == text) {
+                Some((service, _)) => format!("{{{{ index .Values.images `{}` }}}}", service),
+                None => text.replace(export.hostname, "{{ .Values.hostname }}")
+            };
+        },
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if key == "imagePullPolicy" && child == "Never" {
+                    *child = json!("{{ .Values.imagePullPolicy }}");
+                } else {
+                    template_helm_values(child, export);
+                }
+            }
+        },
+        Value::Array(items) => items.iter_mut().for_each(|v| template_helm_values(v, export)),
+        _ => {}
+    }
+}
+
+/// Writes a chart named after the app, with a template per object.
+pub fn write_helm_chart(dir: &Path, export: &ExportConfig, manifests: &[String]) {
+    let chart_dir = dir.join(export.app_name);
+
+    write_file(&chart_dir.join("Chart.yaml"), &to_yaml(&json!({
+        "apiVersion": "v2",
+        "name": export.app_name,
+        "version": "0.1.0"
+    })));
+
+    let images: serde_json::Map<String, Value> = export.images.iter()
+        .map(|(service, image)| (service.clone(), Value::String(image.clone())))
+        .collect();
+    write_file(&chart_dir.join("values.yaml"), &to_yaml(&json!({
+        "hostname": export.hostname,
+        "imagePullPolicy": "Never",
+        "images": images
+    })));
+
+    for (object, mut value) in namespaced_objects(manifests) {
+        template_helm_values(&mut value, export);
+        write_file(&chart_dir.join("templates").join(create_object_file_name(&object)), &to_yaml(&value));
+    }
+}
+
+/// Collects the paths, in Kustomize field path syntax, of the strings equal to `needle`.
+fn find_string_paths(value: &Value, needle: &str, path: &str, acc: &mut Vec<String>) {
+    let join = |key: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+
+    match value {
+        Value::String(s) if s == needle => acc.push(path.to_string()),
+        Value::Object(map) => map.iter().for_each(|(k, v)| find_string_paths(v, needle, &join(k), acc)),
+        Value::Array(items) => items.iter().enumerate().for_each(|(i, v)| find_string_paths(v, needle, &join(&i.to_string()), acc)),
+        _ => {}
+    }
+}
+
+/// Writes a kustomization with a resource per object.
+///
+/// Images are overridable through `images`, the hostname through the `hostname` key of the
+/// `<app>-export-values` ConfigMap, which is never applied to the cluster.
+pub fn write_kustomization(dir: &Path, export: &ExportConfig, manifests: &[String]) {
+    let values_name = format!("{}-export-values", export.app_name);
+    let mut resources = Vec::new();
+    let mut replacement_targets = Vec::new();
+
+    for (object, value) in namespaced_objects(manifests) {
+        let file_name = create_object_file_name(&object);
+        write_file(&dir.join(&file_name), &to_yaml(&value));
+        resources.push(file_name);
+
+        let mut paths = Vec::new();
+        find_string_paths(&value, export.hostname, "", &mut paths);
+        if !paths.is_empty() {
+            replacement_targets.push(json!({
+                "select": { "kind": object.kind, "name": object.name },
+                "fieldPaths": paths
+            }));
+        }
+    }
+
+    let mut kustomization = json!({
+        "apiVersion": "kustomize.config.k8s.io/v1beta1",
+        "kind": "Kustomization",
+        "namespace": export.namespace,
+        "resources": resources,
+        "images": export.images.iter().map(|(_, image)| {
+            let (name, tag) = image.split_once(':').unwrap_or((image, "latest"));
+            json!({ "name": name, "newName": name, "newTag": tag })
+        }).collect::<Vec<Value>>()
+    });
+
+    if !replacement_targets.is_empty() {
+        write_file(&dir.join("values.yaml"), &to_yaml(&json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "name": values_name,
+                "annotations": { "config.kubernetes.io/local-config": "true" }
+            },
+            "data": { "hostname": export.hostname }
+        })));
+        kustomization["resources"].as_array_mut().unwrap().push(json!("values.yaml"));
+        kustomization["replacements"] = json!([{
+            "source": { "kind": "ConfigMap", "name": values_name, "fieldPath": "data.hostname" },
+            "targets": replacement_targets
+        }]);
+    }
+
+    write_file(&dir.join("kustomization.yaml"), &to_yaml(&kustomization));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_export() -> ExportConfig<'static> {
+        ExportConfig {
+            app_name: "shop",
+            namespace: "shop",
+            hostname: "shop.test",
+            images: vec!(("user-api".to_string(), "shop-user-api:latest".to_string()))
+        }
+    }
+
+    #[test]
+    fn helm_values_template_images_and_hostname() {
+        let mut value = json!({
+            "spec": {
+                "rules": [{ "host": "shop.test" }],
+                "containers": [{
+                    "image": "shop-user-api:latest",
+                    "imagePullPolicy": "Never",
+                    "env": [{ "name": "ORIGIN", "value": "https://shop.test/login" }]
+                }]
+            }
+        });
+        template_helm_values(&mut value, &create_export());
+
+        assert_eq!(value["spec"]["rules"][0]["host"], "{{ .Values.hostname }}");
+        let container = &value["spec"]["containers"][0];
+        assert_eq!(container["image"], "{{ index .Values.images `user-api` }}");
+        assert_eq!(container["imagePullPolicy"], "{{ .Values.imagePullPolicy }}");
+        assert_eq!(container["env"][0]["value"], "https://{{ .Values.hostname }}/login");
+    }
+
+    #[test]
+    fn helm_values_keep_keys_and_braces() {
+        let mut value = json!({
+            "data": {
+                "shop.test": "shop.test",
+                "template": "Hello {{ name }}"
+            }
+        });
+        template_helm_values(&mut value, &create_export());
+
+        assert_eq!(value["data"]["shop.test"], "{{ .Values.hostname }}");
+        assert_eq!(value["data"]["template"], "Hello {{ `{{` }} name }}");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::Faults;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// File the proxy of a service reads its faults from on every request.
+pub fn get_faults_file(project_location: &str, service_name: &str) -> PathBuf {
+    Path::new(project_location).join(".minitel/run").join(format!("{}.faults.json", service_name))
+}
+
+/// Faults currently injected, none when the file is missing or unreadable.
+pub fn read_faults(file: &Path) -> Faults {
+    fs::read_to_string(file).ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+pub fn write_faults(file: &Path, faults: &Faults) {
+    fs::create_dir_all(file.parent().unwrap()).expect("Could not create run directory");
+    fs::write(file, serde_json::to_string(faults).unwrap()).expect("Could not write faults file");
+}
+
+/// Splits `500ms` into `500` and `ms`.
+fn split_unit(value: &str) -> (&str, String) {
+    let value = value.trim();
+    let index = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    (&value[..index], value[index..].trim().to_lowercase())
+}
+
+/// Parses a latency such as `500ms`, `2s` or `1m`. A plain number is in milliseconds.
+pub fn parse_latency(value: &str) -> Result<Duration, String> {
+    let (number, unit) = split_unit(value);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid latency {}", value))?;
+    let millis = match unit.as_str() {
+        "" | "ms" => number,
+        "s" => number * 1000.0,
+        "m" => number * 60_000.0,
+        _ => return Err(format!("Invalid latency unit in {}, expected ms, s or m", value))
+    };
+
+    Ok(Duration::from_millis(millis as u64))
+}
+
+/// Parses a bandwidth such as `512`, `10kb` or `1mb`, in bytes per second.
+pub fn parse_bandwidth(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(value);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid bandwidth {}", value))?;
+    let bytes = match unit.trim_end_matches("/s") {
+        "" | "b" => number,
+        "kb" => number * 1024.0,
+        "mb" => number * 1024.0 * 1024.0,
+        _ => return Err(format!("Invalid bandwidth unit in {}, expected b, kb or mb", value))
+    };
+
+    if bytes < 1.0 {
+        return Err(format!("Bandwidth {} is below one byte per second", value));
+    }
+    Ok(bytes as u64)
+}
+
+pub fn validate_faults(faults: &Faults) -> Result<(), String> {
+    if let Some(latency) = &faults.latency {
+        parse_latency(latency)?;
+    }
+    if let Some(bandwidth) = &faults.bandwidth {
+        parse_bandwidth(bandwidth)?;
+    }
+    for (name, rate) in [("error-rate", faults.error_rate), ("reset-rate", faults.reset_rate)] {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(format!("{} must be between 0 and 1, not {}", name, rate));
+        }
+    }
+    if let Some(status) = faults.error_status.iter().find(|s| !(100..=599).contains(*s)) {
+        return Err(format!("Invalid error status {}", status));
+    }
+
+    Ok(())
+}
+
+/// True for a share `rate` of the calls.
+pub fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+/// Status of an injected error, picked at random among `error-status`.
+pub fn pick_error_status(faults: &Faults) -> u16 {
+    if faults.error_status.is_empty() {
+        500
+    } else {
+        faults.error_status[rand::random::<usize>() % faults.error_status.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_latency_units() {
+        assert_eq!(parse_latency("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_latency("250"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_latency("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_latency(" 2 S "), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_latency("1m"), Ok(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn parse_latency_rejects_invalid_values() {
+        assert_eq!(parse_latency("fast"), Err(String::from("Invalid latency fast")));
+        assert_eq!(parse_latency("2h"), Err(String::from("Invalid latency unit in 2h, expected ms, s or m")));
+        assert!(parse_latency("").is_err());
+    }
+
+    #[test]
+    fn parse_bandwidth_units() {
+        assert_eq!(parse_bandwidth("512"), Ok(512));
+        assert_eq!(parse_bandwidth("512b"), Ok(512));
+        assert_eq!(parse_bandwidth("10kb"), Ok(10 * 1024));
+        assert_eq!(parse_bandwidth("10KB/s"), Ok(10 * 1024));
+        assert_eq!(parse_bandwidth("1.5mb"), Ok(1536 * 1024));
+    }
+
+    #[test]
+    fn parse_bandwidth_rejects_invalid_values() {
+        assert_eq!(parse_bandwidth("10gb"), Err(String::from("Invalid bandwidth unit in 10gb, expected b, kb or mb")));
+        assert_eq!(parse_bandwidth("0"), Err(String::from("Bandwidth 0 is below one byte per second")));
+        assert!(parse_bandwidth("kb").is_err());
+    }
+
+    #[test]
+    fn validate_faults_accepts_defaults_and_valid_faults() {
+        assert_eq!(validate_faults(&Faults::default()), Ok(()));
+
+        let faults = Faults {
+            latency: Some(String::from("200ms")),
+            error_rate: 0.5,
+            error_status: vec!(503, 504),
+            reset_rate: 1.0,
+            bandwidth: Some(String::from("1mb"))
+        };
+        assert_eq!(validate_faults(&faults), Ok(()));
+    }
+
+    #[test]
+    fn validate_faults_rejects_invalid_faults() {
+        let invalid = |faults: Faults| validate_faults(&faults).unwrap_err();
+
+        assert_eq!(invalid(Faults { latency: Some(String::from("soon")), ..Faults::default() }), "Invalid latency soon");
+        assert_eq!(invalid(Faults { bandwidth: Some(String::from("0.5")), ..Faults::default() }), "Bandwidth 0.5 is below one byte per second");
+        assert_eq!(invalid(Faults { error_rate: 1.5, ..Faults::default() }), "error-rate must be between 0 and 1, not 1.5");
+        assert_eq!(invalid(Faults { reset_rate: -0.1, ..Faults::default() }), "reset-rate must be between 0 and 1, not -0.1");
+        assert_eq!(invalid(Faults { error_status: vec!(500, 42), ..Faults::default() }), "Invalid error status 42");
+    }
+}
@@ -1,5 +1,4 @@
 use crate::Definition;
-use crate::GlobalConfig;
 
 use std::io::BufWriter;
 use std::io::Write;
@@ -9,18 +8,16 @@ use std::io::{self, BufRead, BufReader};
 /// Checks if hosts file is up-to-date.
 /// 
 /// Returns true if it is, false if it's not
-pub fn check_etc_hosts(config: &GlobalConfig, def: &Definition) -> io::Result<bool> {
+pub fn check_etc_hosts(minikube_ip: &str, def: &Definition) -> io::Result<bool> {
     let file = File::open("/etc/hosts")?;
 
     // Remove entry if there is already one
-    for line in BufReader::new(file).lines() {
-        if let Ok(l) = line {
-            if l.contains(&def.hostname) {
-                if l.contains(&config.minikube_ip) {
-                    return Ok(true);
-                } else {
-                    return Ok(false);
-                }
+    for l in BufReader::new(file).lines().map_while(Result::ok) {
+        if l.contains(&def.hostname) {
+            if l.contains(minikube_ip) {
+                return Ok(true);
+            } else {
+                return Ok(false);
             }
         }
     }
@@ -28,21 +25,19 @@ pub fn check_etc_hosts(config: &GlobalConfig, def: &Definition) -> io::Result<bo
     Ok(false)
 }
 
-pub fn patch_etc_hosts(config: &GlobalConfig, def: &Definition) -> io::Result<()> {
+pub fn patch_etc_hosts(minikube_ip: &str, def: &Definition) -> io::Result<()> {
     let file = File::open("/etc/hosts")?;
     
     let mut acc: Vec<String> = Vec::new();
 
     // Remove entry if there is already one
-    for line in BufReader::new(file).lines() {
-        if let Ok(l) = line {
-            if !l.contains(&def.hostname) {
-                acc.push(l);
-            }
+    for l in BufReader::new(file).lines().map_while(Result::ok) {
+        if !l.contains(&def.hostname) {
+            acc.push(l);
         }
     }
 
-    let entry = format!("{} {} # minitel application", &config.minikube_ip, &def.hostname);
+    let entry = format!("{} {} # minitel application", minikube_ip, &def.hostname);
     acc.push(entry);
 
     let file = File::create("/etc/hosts")?;
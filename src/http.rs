@@ -0,0 +1,98 @@
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+
+/// An HTTP/1.1 message, with its body decoded if it was chunked.
+pub struct HttpMessage {
+    pub start_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+impl HttpMessage {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn is_chunked(&self) -> bool {
+        self.header("transfer-encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    }
+
+    /// Method of a request.
+    pub fn method(&self) -> &str {
+        self.start_line.split(' ').next().unwrap_or_default()
+    }
+
+    /// Path of a request, without its query.
+    pub fn path(&self) -> &str {
+        let target = self.start_line.split(' ').nth(1).unwrap_or("/");
+        target.split('?').next().unwrap_or("/")
+    }
+}
+
+fn read_head<R: BufRead>(reader: &mut R) -> Option<(String, Vec<(String, String)>)> {
+    let mut start_line = String::new();
+    if reader.read_line(&mut start_line).ok()? == 0 {
+        return None;
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    Some((start_line.trim_end().to_string(), headers))
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line).ok()?;
+        let size = usize::from_str_radix(size_line.trim().split(';').next()?, 16).ok()?;
+
+        let mut chunk = vec!(0; size + 2);
+        reader.read_exact(&mut chunk).ok()?;
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(&chunk[..size]);
+    }
+}
+
+pub fn read_request<R: BufRead>(reader: &mut R) -> Option<HttpMessage> {
+    let (start_line, headers) = read_head(reader)?;
+    let mut request = HttpMessage { start_line, headers, body: Vec::new() };
+
+    if request.is_chunked() {
+        request.body = read_chunked(reader)?;
+    } else if let Some(length) = request.header("content-length").and_then(|l| l.parse().ok()) {
+        request.body = vec!(0; length);
+        reader.read_exact(&mut request.body).ok()?;
+    }
+
+    Some(request)
+}
+
+/// Parses a response read until the upstream closed the connection.
+pub fn parse_response(raw: &[u8]) -> Option<HttpMessage> {
+    let mut reader = BufReader::new(raw);
+    let (start_line, headers) = read_head(&mut reader)?;
+    let mut response = HttpMessage { start_line, headers, body: Vec::new() };
+
+    if response.is_chunked() {
+        response.body = read_chunked(&mut reader).unwrap_or_default();
+    } else {
+        reader.read_to_end(&mut response.body).ok()?;
+    }
+
+    Some(response)
+}
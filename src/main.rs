@@ -4,28 +4,131 @@ mod minikube;
 mod k8s;
 mod docker;
 mod dev;
+mod watch;
+mod cluster;
+mod diff;
+mod template;
+mod charts;
+mod export;
+mod tunnel;
+mod discovery;
+mod tls;
+mod mock;
+mod proxy;
+mod fault;
+mod background;
+mod http;
 
 use dev::build_development_env;
 use dev::start_development_service;
 use dev::stop_development_service;
+use dev::is_development_service_started;
+use dev::run_development_command;
+use dev::exec_development_command;
 use docker::build_service_image;
+use docker::ContainerConfig;
+use docker::run_service_container;
+use docker::run_job_container;
+use docker::remove_service_container;
+use docker::exec_service_container;
+use docker::remove_app_containers;
+use dev::build_container_env;
+use dev::CONTAINER_HOST_ALIAS;
 use k8s::ensure_tag_config;
+use k8s::extra_apply_rank;
+use k8s::create_origin_service_name;
 use minikube::get_minikube_ip;
 use k8s::build_routing_definition;
 use k8s::build_service_cluster_definition;
-use k8s::kubectl_delete_all;
-use k8s::kubectl_apply;
+use k8s::kubectl_cp;
+use k8s::kubectl_exec;
+use k8s::kubectl_exec_attached;
+use k8s::kubectl_logs_follow;
+use k8s::create_volume_claim_name;
+use k8s::build_volume_claim_definition;
+use k8s::is_seeded_claim;
+use k8s::build_job_definition;
+use k8s::create_job_name;
 use minikube::get_host_ip;
+use minikube::get_minikube_profile;
+use minikube::get_mount_node_path;
+use minikube::start_mount;
+use minikube::stop_mount;
+use minikube::stop_all_mounts;
 use k8s::build_service_local_definition;
 use k8s::K8sServiceConfig;
+use k8s::SourceMount;
+use k8s::KubeTarget;
+use k8s::default_namespace;
+use k8s::build_namespace_definition;
+use k8s::is_owned_namespace;
+use k8s::create_app_selector;
+use k8s::create_image_name;
+use k8s::SERVICE_PORT;
+use discovery::list_named_ports;
+use mock::get_mocks_dir;
+use fault::get_faults_file;
+use fault::read_faults;
+use fault::validate_faults;
+use fault::write_faults;
+use proxy::find_proxy_port;
+use proxy::Interception;
+use proxy::get_recording_file;
+use proxy::load_recording;
+use proxy::replay_entry;
+use proxy::run_proxy;
+use proxy::start_proxy;
+use proxy::stop_all_proxies;
+use dev::create_local_address;
+use tunnel::find_tunnel_ports;
+use tunnel::start_tunnel_to;
+use mock::run_mock_server;
+use mock::start_mock_server;
+use mock::stop_mock_server;
+use mock::stop_all_mock_servers;
+use tls::ensure_ca;
+use tls::ensure_certificate;
+use tls::get_ca_cert_path;
+use k8s::build_tls_secret_definition;
+use k8s::add_ingress_tls;
+use k8s::create_config_map_ref;
 use std::io;
 use std::process::Command;
 use std::collections::HashMap;
 use std::path::Path;
+use std::path::PathBuf;
 use std::env;
 use std::fs;
 use std::str;
 use std::io::ErrorKind;
+use std::io::Write;
+use std::io::IsTerminal;
+use std::process;
+use std::cell::OnceCell;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use watch::Watcher;
+use template::build_template_context;
+use template::render_template;
+use charts::render_chart;
+use export::ExportFormat;
+use export::ExportConfig;
+use export::write_yaml_bundle;
+use export::write_helm_chart;
+use export::write_kustomization;
+use tunnel::start_tunnel;
+use tunnel::stop_all_tunnels;
+use cluster::ClusterApi;
+use cluster::KubeClient;
+use cluster::RecordingCluster;
+use cluster::OfflineCluster;
+use cluster::ObjectRef;
+use cluster::JobStatus;
+use cluster::PodState;
+use cluster::list_contexts;
+use cluster::parse_manifests;
+use diff::diff_object;
 extern crate yaml_rust;
 
 use models::*;
@@ -48,7 +151,7 @@ fn load_definition(path: &str) -> Definition {
         .expect("Could not read minitel file");
     let parsed: Definition = serde_yaml::from_str(&contents).unwrap();
 
-    return parsed;
+    parsed
 }
 
 fn load_config(path: &str) -> HashMap<String, ServiceConfig> {
@@ -64,53 +167,301 @@ fn load_config(path: &str) -> HashMap<String, ServiceConfig> {
     }
 }
 
-fn deploy_to_cluster(global_config: &GlobalConfig, service: &Service) {
+/// A loaded project and the cluster it runs against, what the commands start from.
+struct Project {
+    location: String,
+    def: Definition,
+    cfg: HashMap<String, ServiceConfig>,
+    kube: KubeTarget,
+    /// Looked up on first use, through minikube
+    host_ip: OnceCell<String>,
+    minikube_ip: OnceCell<String>
+}
+
+impl Project {
+    fn host_ip(&self) -> &str {
+        self.host_ip.get_or_init(|| get_host_ip(&self.kube.context))
+    }
+
+    fn minikube_ip(&self) -> &str {
+        self.minikube_ip.get_or_init(|| get_minikube_ip(&self.kube.context))
+    }
+
+    fn global_config<'a>(&'a self, cluster: &'a dyn ClusterApi, config_hash: &'a str) -> GlobalConfig<'a> {
+        GlobalConfig {
+            host_ip: self.host_ip(),
+            minikube_ip: self.minikube_ip(),
+            app_name: &self.def.app_name,
+            hostname: &self.def.hostname,
+            config_hash,
+            project_location: &self.location,
+            kube: &self.kube,
+            cluster
+        }
+    }
+}
+
+/// Loads the project around the working directory, pinned to its kube context.
+fn load_project(force_context: bool) -> Project {
+    let location = locate_project().expect("Could not find project root.");
+    let def = load_definition(&location);
+    let cfg = load_config(&location);
+    let kube = create_kube_target(&def, force_context);
+
+    Project { location, def, cfg, kube, host_ip: OnceCell::new(), minikube_ip: OnceCell::new() }
+}
+
+/// Loads the project without checking its kube context, which may not exist on this machine.
+///
+/// Only services on the host need the addresses of minikube, without them the project is cluster agnostic.
+fn load_offline_project() -> Project {
+    let location = locate_project().expect("Could not find project root.");
+    let def = load_definition(&location);
+    let cfg = load_config(&location);
 
-    build_service_image(&global_config.project_location, &global_config.app_name, &service.name);
+    let has_host_services = def.services.iter()
+        .any(|s| cfg.get(&s.name).is_some_and(|c| c.deploy.is_on_host()));
+    let kube = KubeTarget {
+        context: match &def.context {
+            Some(context) => context.clone(),
+            None if has_host_services => get_minikube_profile(),
+            None => String::new()
+        },
+        namespace: def.namespace.clone().unwrap_or_else(|| default_namespace(&def.app_name))
+    };
+    let (host_ip, minikube_ip) = (OnceCell::new(), OnceCell::new());
+    if !has_host_services {
+        host_ip.get_or_init(String::new);
+        minikube_ip.get_or_init(String::new);
+    }
+
+    Project { location, def, cfg, kube, host_ip, minikube_ip }
+}
 
-    let config = build_service_cluster_definition(& K8sServiceConfig {
-        host_ip: &global_config.host_ip,
+fn build_k8s_service_config<'a>(global_config: &'a GlobalConfig, service: &'a Service, config: &'a ServiceConfig) -> K8sServiceConfig<'a> {
+    K8sServiceConfig {
+        host_ip: global_config.host_ip,
         service_name: &service.name,
         service_port: service.port,
+        named_ports: list_named_ports(service),
         app_name: global_config.app_name,
-        config_hash: &global_config.config_hash
-    });
+        config_hash: global_config.config_hash,
+        replicas: config.replicas.or(service.replicas).unwrap_or(1),
+        resources: config.resources.as_ref().or(service.resources.as_ref()),
+        command: config.command.as_ref().or(service.command.as_ref()),
+        args: config.args.as_ref().or(service.args.as_ref()),
+        labels: config.labels.as_ref().or(service.labels.as_ref()),
+        annotations: config.annotations.as_ref().or(service.annotations.as_ref()),
+        source_mount: None,
+        volumes: service.volumes.as_ref(),
+        intercept_port: None,
+        debug: None
+    }
+}
 
-    kubectl_apply(&config);
+fn create_volume_claim_ref(service: &Service, volume: &Volume) -> ObjectRef {
+    ObjectRef {
+        api_version: String::from("v1"),
+        kind: String::from("PersistentVolumeClaim"),
+        name: create_volume_claim_name(&service.name, &volume.name),
+        namespace: None
+    }
 }
 
-fn deploy_local(global_config: &GlobalConfig, service: &Service, env: &HashMap<String, String>) {
+fn is_volume_seeded(cluster: &dyn ClusterApi, service: &Service, volume: &Volume) -> bool {
+    cluster.get(&create_volume_claim_ref(service, volume)).is_some_and(|claim| is_seeded_claim(&claim))
+}
 
-    let config = build_service_local_definition(& K8sServiceConfig {
-        host_ip: &global_config.host_ip,
-        service_name: &service.name,
-        service_port: service.port,
+/// Applies the volume claims of a service and returns the volumes whose seed did not complete yet.
+fn apply_volumes<'a>(global_config: &GlobalConfig, service: &'a Service) -> Vec<&'a Volume> {
+    let mut unseeded = Vec::new();
+
+    for volume in service.volumes.iter().flatten() {
+        let seeded = is_volume_seeded(global_config.cluster, service, volume);
+        if volume.seed.is_some() && !seeded {
+            unseeded.push(volume);
+        }
+        global_config.cluster.apply(&build_volume_claim_definition(global_config.app_name, &service.name, volume, seeded));
+    }
+
+    unseeded
+}
+
+fn seed_volumes(global_config: &GlobalConfig, service: &Service, volumes: &[&Volume]) {
+    let seeds: Vec<(&Volume, &Seed)> = volumes.iter()
+        .filter_map(|v| v.seed.as_ref().map(|seed| (*v, seed)))
+        .collect();
+
+    if seeds.is_empty() {
+        return;
+    }
+
+    global_config.cluster.wait_for_rollout(&service.name);
+    let pod = global_config.cluster.find_running_pod(&format!("app={},tier={}", global_config.app_name, service.name))
+        .unwrap_or_else(|| panic!("Could not find a running pod of {} to seed its volumes", service.name));
+
+    for (volume, seed) in seeds {
+        println!("Seeding volume {} of {}...", volume.name, service.name);
+
+        if let Some(files) = &seed.files {
+            let source = Path::new(global_config.project_location).join("services").join(&service.name).join(files);
+            kubectl_cp(global_config.kube, &format!("{}/.", source.to_str().unwrap()), &pod, &volume.path);
+        }
+        if let Some(command) = &seed.command {
+            kubectl_exec(global_config.kube, &pod, command);
+        }
+        global_config.cluster.apply(&build_volume_claim_definition(global_config.app_name, &service.name, volume, true));
+    }
+}
+
+/// Renders the Service and Deployment (or Endpoints) of a service, as `up` applies them.
+fn render_service(global_config: &GlobalConfig, service: &Service, service_config: &ServiceConfig) -> String {
+    let mut k8s_config = build_k8s_service_config(global_config, service, service_config);
+    // The Service fronts the proxy once it runs, see `configure_proxies`
+    k8s_config.intercept_port = service_config.intercept.as_ref()
+        .and(find_proxy_port(global_config.project_location, &service.name))
+        .map(i32::from);
+    k8s_config.debug = service_config.debug.as_ref();
+
+    match service_config.deploy {
+        DeployMode::Cluster => build_service_cluster_definition(&k8s_config),
+        DeployMode::Local | DeployMode::Docker | DeployMode::Mock => build_service_local_definition(&k8s_config),
+        DeployMode::Sync => {
+            let node_path = get_mount_node_path(global_config.app_name, &service.name);
+            k8s_config.source_mount = Some(SourceMount {
+                node_path: &node_path,
+                container_path: service.sync_path.as_ref()
+                    .unwrap_or_else(|| panic!("Service {} needs a sync-path to be deployed in sync mode", service.name))
+            });
+            build_service_cluster_definition(&k8s_config)
+        }
+    }
+}
+
+fn deploy_to_cluster(global_config: &GlobalConfig, service: &Service, service_config: &ServiceConfig) {
+
+    build_service_image(global_config.project_location, global_config.app_name, &service.name);
+
+    let new_volumes = apply_volumes(global_config, service);
+
+    global_config.cluster.apply(&render_service(global_config, service, service_config));
+
+    seed_volumes(global_config, service, &new_volumes);
+}
+
+fn deploy_sync(global_config: &GlobalConfig, service: &Service, service_config: &ServiceConfig) {
+    let node_path = get_mount_node_path(global_config.app_name, &service.name);
+    let source = Path::new(global_config.project_location).join("services").join(&service.name);
+
+    start_mount(global_config.project_location, &global_config.kube.context, &service.name, source.to_str().unwrap(), &node_path);
+
+    deploy_to_cluster(global_config, service, service_config);
+}
+
+fn deploy_local(global_config: &GlobalConfig, service: &Service, service_config: &ServiceConfig, env: &HashMap<String, String>) {
+
+    global_config.cluster.apply(&render_service(global_config, service, service_config));
+
+    stop_development_service(global_config.project_location, service, env);
+    start_development_service(global_config.project_location, service, env, service_config.debug.as_ref());
+}
+
+/// Container settings of a service run in Docker on the host, which reaches the cluster like the host does.
+fn build_container_config<'a>(global_config: &'a GlobalConfig, service: &'a Service, command: Option<&'a Vec<String>>, args: Option<&'a Vec<String>>, env: &'a HashMap<String, String>) -> ContainerConfig<'a> {
+    ContainerConfig {
         app_name: global_config.app_name,
-        config_hash: &global_config.config_hash
-    });
+        service_name: &service.name,
+        env,
+        hosts: vec!(
+            (String::from(CONTAINER_HOST_ALIAS), String::from("host-gateway")),
+            (String::from(global_config.hostname), String::from(global_config.minikube_ip))
+        ),
+        command,
+        args
+    }
+}
+
+/// Runs the image of a service in Docker on the host, the cluster reaching it through its Endpoints.
+///
+/// Volumes are Docker named volumes, they are not seeded.
+fn deploy_docker(global_config: &GlobalConfig, service: &Service, service_config: &ServiceConfig, env: &HashMap<String, String>) {
+    build_service_image(global_config.project_location, global_config.app_name, &service.name);
+
+    let source = Path::new(global_config.project_location).join("services").join(&service.name);
+    let mut mounts = vec!((
+        String::from(source.to_str().unwrap()),
+        service.sync_path.clone()
+            .unwrap_or_else(|| panic!("Service {} needs a sync-path to be deployed in docker mode", service.name))
+    ));
+    for volume in service.volumes.iter().flatten() {
+        let name = format!("{}-{}", global_config.app_name, create_volume_claim_name(&service.name, &volume.name));
+        mounts.push((name, volume.path.clone()));
+    }
+
+    let env = build_container_env(env);
+    let command = service_config.command.as_ref().or(service.command.as_ref());
+    let args = service_config.args.as_ref().or(service.args.as_ref());
+    run_service_container(&build_container_config(global_config, service, command, args, &env), service.port, &mounts);
 
-    kubectl_apply(&config);
+    global_config.cluster.apply(&render_service(global_config, service, service_config));
+}
+
+/// Replaces a service with `minitel mock-server` on the host, the cluster reaching it through its Endpoints.
+fn deploy_mock(global_config: &GlobalConfig, service: &Service, service_config: &ServiceConfig) {
+    start_mock_server(global_config.project_location, &service.name);
 
-    stop_development_service(&global_config.project_location, &service, env);
-    start_development_service(&global_config.project_location, &service, env);
+    global_config.cluster.apply(&render_service(global_config, service, service_config));
 }
 
 fn start_service(global_config: &GlobalConfig, service: &Service, config: &ServiceConfig, env: &HashMap<String, String>) {
+    if !matches!(config.deploy, DeployMode::Docker) {
+        remove_service_container(global_config.app_name, &service.name);
+    }
+    if !matches!(config.deploy, DeployMode::Mock) {
+        stop_mock_server(global_config.project_location, &service.name);
+    }
+    // A process left from local mode or from an interception would keep holding the port
+    if !matches!(config.deploy, DeployMode::Local) && config.intercept.is_none()
+        && is_development_service_started(global_config.project_location, service) {
+        stop_development_service(global_config.project_location, service, env);
+    }
+
     match config.deploy {
-        DeployMode::Cluster => deploy_to_cluster(global_config, service),
-        DeployMode::Local => deploy_local(global_config, service, env)
+        DeployMode::Cluster => {
+            stop_mount(global_config.project_location, &service.name);
+            deploy_to_cluster(global_config, service, config)
+        },
+        DeployMode::Local => {
+            stop_mount(global_config.project_location, &service.name);
+            deploy_local(global_config, service, config, env)
+        },
+        DeployMode::Sync => deploy_sync(global_config, service, config),
+        DeployMode::Docker => {
+            stop_mount(global_config.project_location, &service.name);
+            deploy_docker(global_config, service, config, env)
+        },
+        DeployMode::Mock => {
+            stop_mount(global_config.project_location, &service.name);
+            deploy_mock(global_config, service, config)
+        }
+    }
+
+    // The local process answers the intercepted requests
+    if config.intercept.is_some() && !config.deploy.is_on_host() {
+        stop_development_service(global_config.project_location, service, env);
+        start_development_service(global_config.project_location, service, env, config.debug.as_ref());
     }
 }
 
-fn run_patch_hosts_as_sudo() {
-    let program = env::args().nth(0).expect("Bad cli arguments");
+fn run_patch_hosts_as_sudo(minikube_ip: &str) {
+    let program = env::args().next().expect("Bad cli arguments");
 
     println!("I need to patch /etc/hosts to map the app's hostname to the minikube ip address.");
     println!("This requires root access. You might be prompted for your password...");
 
     let output = Command::new("sh")
         .arg("-c")
-        .arg(format!("sudo {} patch-hosts", program))
+        .arg(format!("sudo {} patch-hosts {}", program, minikube_ip))
         .output()
         .expect("Failed to execute command");
 
@@ -119,101 +470,983 @@ fn run_patch_hosts_as_sudo() {
     }
 }
 
-fn run_down() {
-    println!("Destroying all state...");
+fn run_job(global_config: &GlobalConfig, def: &Definition, cfg: &HashMap<String, ServiceConfig>, job: &Job, env: &HashMap<String, String>) -> bool {
+    let service = def.services.iter().find(|s| s.name == job.service)
+        .unwrap_or_else(|| panic!("Job {} refers to unknown service {}", job.name, job.service));
+
+    for dependency in &job.wait_for {
+        if !cfg.get(dependency).is_some_and(|s| s.deploy.is_on_host()) {
+            global_config.cluster.wait_for_rollout(dependency);
+        }
+    }
+
+    println!("Running job {}...", job.name);
+
+    match cfg.get(&service.name).map(|s| &s.deploy) {
+        Some(DeployMode::Local) => run_development_command(global_config.project_location, service, &job.command, env),
+        Some(DeployMode::Docker) => {
+            build_service_image(global_config.project_location, global_config.app_name, &service.name);
+            let env = build_container_env(env);
+            run_job_container(&build_container_config(global_config, service, Some(&job.command), None, &env))
+        },
+        Some(DeployMode::Mock) => {
+            println!("Skipping job {}, service {} is mocked", job.name, service.name);
+            true
+        },
+        _ => {
+            build_service_image(global_config.project_location, global_config.app_name, &service.name);
+
+            let job_ref = ObjectRef {
+                api_version: String::from("batch/v1"),
+                kind: String::from("Job"),
+                name: create_job_name(&job.name),
+                namespace: None
+            };
+
+            // Jobs are immutable, the previous run must be gone before it can be replaced
+            global_config.cluster.delete(&job_ref);
+            while global_config.cluster.get(&job_ref).is_some() {
+                thread::sleep(Duration::from_secs(1));
+            }
+
+            global_config.cluster.apply(&build_job_definition(global_config.app_name, global_config.config_hash, job));
+            let job_uid = global_config.cluster.get(&job_ref)
+                .and_then(|live| live.pointer("/metadata/uid").and_then(|uid| uid.as_str()).map(String::from))
+                .unwrap_or_else(|| panic!("Job {} disappeared after it was created", job.name));
+            follow_job(global_config, &job_ref.name, &job_uid)
+        }
+    }
+}
+
+/// Reasons a container waits for which it will not start without a change, such as a missing image.
+const POD_START_ERRORS: [&str; 6] = [
+    "ErrImageNeverPull", "ErrImagePull", "ImagePullBackOff", "InvalidImageName", "CreateContainerConfigError", "CreateContainerError"
+];
+const JOB_START_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Streams the output of a job until it finishes. Returns true if the job succeeded.
+///
+/// The pod is selected by the uid of the Job, so that one of a previous run still being deleted is never picked.
+fn follow_job(global_config: &GlobalConfig, job_name: &str, job_uid: &str) -> bool {
+    let selector = format!("controller-uid={}", job_uid);
+    let started = Instant::now();
+
+    // Logs can only be streamed once the pod has left the Pending phase
+    loop {
+        match global_config.cluster.pod_state(&selector) {
+            Some(state) if state.phase != "Pending" => break,
+            Some(PodState { waiting_reason: Some(reason), .. }) if POD_START_ERRORS.contains(&reason.as_str()) => {
+                println!("Job {} cannot start: {}", job_name, reason);
+                return false;
+            }
+            _ if started.elapsed() > JOB_START_TIMEOUT => {
+                println!("Job {} did not start within {} seconds", job_name, JOB_START_TIMEOUT.as_secs());
+                return false;
+            }
+            _ => thread::sleep(Duration::from_secs(1))
+        }
+    }
+
+    kubectl_logs_follow(global_config.kube, &format!("job/{}", job_name));
+
+    loop {
+        match global_config.cluster.job_status(job_name) {
+            JobStatus::Succeeded => return true,
+            JobStatus::Failed => return false,
+            JobStatus::Running => thread::sleep(Duration::from_secs(1))
+        }
+    }
+}
+
+/// Runs the jobs matching the filter in order, aborting on the first failure.
+fn run_jobs<F: Fn(&Job) -> bool>(global_config: &GlobalConfig, def: &Definition, cfg: &HashMap<String, ServiceConfig>, env: &HashMap<String, String>, filter: F) {
+    for job in def.jobs.iter().filter(|j| filter(j)) {
+        if !run_job(global_config, def, cfg, job, env) {
+            panic!("Job {} failed", job.name);
+        }
+    }
+}
+
+fn is_hook(job: &Job, hook: Hook) -> bool {
+    job.before.is_none() && job.hook.as_ref() == Some(&hook)
+}
+
+fn run_down(purge: bool, force_context: bool) {
+    let project = load_project(force_context);
+    let Project { location: project_location, def, cfg, kube, .. } = &project;
+    let cluster = KubeClient::new(kube);
+
+    println!("Destroying all state of {}...", def.app_name);
+
+    if def.jobs.iter().any(|j| is_hook(j, Hook::PreDown)) {
+        let config_hash = configure_routing(&cluster, def, cfg, &find_proxy_ports(project_location, def));
+        let dev_env = build_development_env(project_location, def, cfg);
+        let config = project.global_config(&cluster, &config_hash);
+
+        run_jobs(&config, def, cfg, &dev_env, |j| is_hook(j, Hook::PreDown));
+    }
 
     // TODO: Clear etc hosts
 
-    kubectl_delete_all();
+    delete_all(&cluster, kube, &def.app_name, purge);
+
+    stop_all_mounts(project_location);
+    stop_all_tunnels(project_location);
+    remove_app_containers(&def.app_name);
+    stop_all_proxies(project_location);
+    stop_all_mock_servers(project_location);
 
     println!("Done.");
 }
 
-fn configure_routing(def: &Definition, cfg: &HashMap<String, ServiceConfig>) -> String {
-    let hash = format!("{}", rand::random::<u16>());
-    let config = build_routing_definition(def, cfg, &hash);
-    kubectl_apply(&config);
+/// Contexts of local development clusters, which minitel may always run against.
+const DEFAULT_ALLOWED_CONTEXTS: [&str; 7] = [
+    "minikube", "docker-desktop", "docker-for-desktop", "rancher-desktop", "colima", "kind-*", "k3d-*"
+];
+
+fn is_allowed_context(def: &Definition, context: &str) -> bool {
+    DEFAULT_ALLOWED_CONTEXTS.iter().copied()
+        .chain(def.allowed_contexts.iter().map(|c| c.as_str()))
+        .any(|allowed| glob::Pattern::new(allowed).is_ok_and(|p| p.matches(context)))
+}
+
+/// Checks that `context` is one of the `existing` contexts, and a local cluster unless `force_context` is set.
+fn validate_context(def: &Definition, context: &str, existing: &[String], force_context: bool) -> Result<(), String> {
+    if !existing.iter().any(|c| c == context) {
+        return Err(format!("Kube context {} does not exist.", context));
+    }
+    if !is_allowed_context(def, context) && !force_context {
+        return Err(format!("Refusing to run against kube context {}. Add it to allowed-contexts in minitel.yaml or pass --force-context.", context));
+    }
+
+    Ok(())
+}
+
+/// Pins the context and namespace of the app. Unless `force_context` is set, only local clusters are allowed.
+fn create_kube_target(def: &Definition, force_context: bool) -> KubeTarget {
+    let context = def.context.clone().unwrap_or_else(get_minikube_profile);
+
+    validate_context(def, &context, &list_contexts(), force_context).unwrap_or_else(|e| panic!("{}", e));
+
+    let namespace = def.namespace.clone().unwrap_or_else(|| default_namespace(&def.app_name));
+    println!("Using kube context {} and namespace {}", context, namespace);
+
+    KubeTarget {
+        context,
+        namespace
+    }
+}
+
+fn create_namespace_ref(namespace: &str) -> ObjectRef {
+    ObjectRef {
+        api_version: String::from("v1"),
+        kind: String::from("Namespace"),
+        name: namespace.to_string(),
+        namespace: None
+    }
+}
+
+/// Creates the namespace of the app. A namespace which already existed, such as `default` or
+/// one shared by a team, is left as it is so that it is never taken for one minitel created.
+fn create_namespace(cluster: &dyn ClusterApi, kube: &KubeTarget, def: &Definition) {
+    match cluster.get(&create_namespace_ref(&kube.namespace)) {
+        Some(live) if !is_owned_namespace(&live, &def.app_name) => {}
+        _ => {
+            cluster.apply(&build_namespace_definition(&def.app_name, &kube.namespace));
+        }
+    }
+}
+
+/// Kinds holding data or other objects, which are never pruned and only deleted by `down --purge`.
+const KEPT_KINDS: [&str; 2] = ["PersistentVolumeClaim", "Namespace"];
+
+/// Deletes everything minitel created for the app. Volume claims and namespaces are only deleted when `purge` is set.
+fn delete_all(cluster: &dyn ClusterApi, kube: &KubeTarget, app_name: &str, purge: bool) {
+    for object in cluster.list(&create_app_selector(app_name)) {
+        if purge || !KEPT_KINDS.contains(&object.kind.as_str()) {
+            cluster.delete(&object);
+        }
+    }
+
+    if purge {
+        let namespace = create_namespace_ref(&kube.namespace);
+        match cluster.get(&namespace) {
+            Some(live) if is_owned_namespace(&live, app_name) => cluster.delete(&namespace),
+            Some(_) => println!("Keeping namespace {}, minitel did not create it", kube.namespace),
+            None => {}
+        }
+    }
+}
+
+fn create_config_hash() -> String {
+    format!("{}", rand::random::<u16>())
+}
+
+fn configure_routing(cluster: &dyn ClusterApi, def: &Definition, cfg: &HashMap<String, ServiceConfig>, proxy_ports: &HashMap<String, u16>) -> String {
+    let hash = create_config_hash();
+    let config = build_routing_definition(def, cfg, proxy_ports, &hash);
+    cluster.apply(&config);
 
     hash
 }
 
-fn validate_and_apply_extras(project_location: &str) {
-    let kubeconfigs = fs::read_dir(Path::new(project_location).join(".minitel/kube"));
+/// Extensions of the files of `.minitel/kube` which are read as manifests.
+const EXTRA_EXTENSIONS: [&str; 3] = ["yaml", "yml", "json"];
+
+/// Lists the manifest files under a directory and its subdirectories, sorted by path.
+fn find_extra_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec!(root.to_path_buf());
 
-    if let Ok(entries) = kubeconfigs {
-        for entry in entries {
-            let path = entry.unwrap().path();
-            if path.is_file() {
-                let config = fs::read_to_string(path).unwrap();
-                kubectl_apply(&ensure_tag_config(config));
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+        for entry in entries.map_while(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().and_then(|e| e.to_str()).is_some_and(|e| EXTRA_EXTENSIONS.contains(&e)) {
+                files.push(path);
+            } else {
+                println!("Ignoring {}, it is not a manifest", path.display());
             }
         }
     }
 
+    files.sort();
+    files
 }
 
-fn run_up() {
+/// Reads the extra manifests of `.minitel/kube`, rendered as templates, and those of the charts,
+/// tagged with the app label. With `dev_certificate`, their Ingresses serve the certificate of the
+/// development CA when the app has `tls` set.
+///
+/// Documents are returned in apply order: custom resource definitions, namespaces, then the rest by file path.
+fn load_extras(global_config: &GlobalConfig, def: &Definition, cfg: &HashMap<String, ServiceConfig>, dev_certificate: bool) -> Vec<String> {
+    let mut extras = Vec::new();
+    let context = build_template_context(global_config, def, cfg);
 
-    let project_location = locate_project().expect("Could not find project root.");
-    let def = load_definition(&project_location);
-    let cfg = load_config(&project_location);
+    for path in find_extra_files(&Path::new(global_config.project_location).join(".minitel/kube")) {
+        let template = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e));
+        let config = render_template(&path.to_string_lossy(), &template, &context)
+            .unwrap_or_else(|e| panic!("Could not render {}: {}", path.display(), e));
+        let documents = ensure_tag_config(&config, global_config.app_name)
+            .unwrap_or_else(|e| panic!("Invalid manifest {}: {}", path.display(), e));
+        extras.extend(documents);
+    }
 
+    for chart in &def.charts {
+        let config = render_chart(global_config.project_location, &global_config.kube.namespace, chart);
+        let documents = ensure_tag_config(&config, global_config.app_name)
+            .unwrap_or_else(|e| panic!("Invalid manifest in chart {}: {}", chart.name, e));
+        extras.extend(documents);
+    }
 
-    let config_hash = configure_routing(&def, &cfg);
-    let dev_env = build_development_env(&def, &cfg);
+    if def.tls && dev_certificate {
+        for extra in extras.iter_mut() {
+            add_ingress_tls(extra, global_config.app_name);
+        }
+    }
 
-    let config = GlobalConfig {
-        host_ip: &get_host_ip(),
-        minikube_ip: &get_minikube_ip(),
-        app_name: &def.app_name,
-        config_hash: &config_hash,
-        project_location: &project_location
+    extras.sort_by_key(extra_apply_rank);
+    extras.iter()
+        .map(|extra| serde_yaml::to_string(extra).expect("Failed to serialize"))
+        .collect()
+}
+
+/// Secret holding the certificate of the hostname, when it is served over HTTPS.
+fn render_tls_secret(project_location: &str, def: &Definition) -> Option<String> {
+    if !def.tls {
+        return None;
+    }
+
+    let hostnames = vec!(def.hostname.clone(), format!("*.{}", def.hostname));
+    let (cert, key) = ensure_certificate(project_location, &hostnames);
+    Some(build_tls_secret_definition(&def.app_name, &cert, &key))
+}
+
+fn validate_and_apply_extras(global_config: &GlobalConfig, def: &Definition, cfg: &HashMap<String, ServiceConfig>) {
+    for extra in load_extras(global_config, def, cfg, true) {
+        global_config.cluster.apply(&extra);
+    }
+}
+
+/// Lists the objects of the app which are not among the desired ones, volume claims and namespaces excepted.
+fn find_prunable(cluster: &dyn ClusterApi, app_name: &str, desired: &[ObjectRef]) -> Vec<ObjectRef> {
+    let is_desired = |object: &ObjectRef| {
+        desired.iter().any(|d| d.is_same_object(object))
+            // Kubernetes maintains the Endpoints of a Service, copying its labels
+            || (object.kind == "Endpoints" && desired.iter().any(|d| d.kind == "Service" && d.name == object.name && d.namespace == object.namespace))
     };
 
-    let default_cfg = ServiceConfig {
-        deploy: DeployMode::Cluster
+    cluster.list(&create_app_selector(app_name)).into_iter()
+        .filter(|object| !KEPT_KINDS.contains(&object.kind.as_str()) && !is_desired(object))
+        .collect()
+}
+
+/// Deletes the objects of the app which were not applied by the current run.
+///
+/// Volume claims and namespaces are kept, they are only deleted by `down --purge`.
+fn prune(cluster: &dyn ClusterApi, app_name: &str, applied: &[ObjectRef]) {
+    for object in find_prunable(cluster, app_name, applied) {
+        println!("Pruning {}, it is no longer part of the project", object);
+        cluster.delete(&object);
+    }
+}
+
+/// Starts a tunnel to every cluster service which asks for one, as long as local services may use it.
+fn configure_tunnels(project_location: &str, kube: &KubeTarget, def: &Definition, cfg: &HashMap<String, ServiceConfig>) {
+    let is_on_host = |service: &Service| cfg.get(&service.name).is_some_and(|s| s.deploy.is_on_host());
+
+    stop_all_tunnels(project_location);
+
+    if !def.services.iter().any(is_on_host) {
+        return;
+    }
+    for service in def.services.iter().filter(|s| s.tunnel && !is_on_host(s)) {
+        let named_ports = list_named_ports(service);
+        let remote_ports: Vec<i32> = std::iter::once(SERVICE_PORT)
+            .chain(named_ports.iter().map(|(_, port)| *port))
+            .collect();
+        let ports = start_tunnel(project_location, kube, &service.name, &remote_ports);
+        println!("Forwarding localhost:{} to {}", ports[0], service.name);
+        for ((name, _), port) in named_ports.iter().zip(&ports[1..]) {
+            println!("Forwarding localhost:{} to {} ({})", port, service.name, name);
+        }
+    }
+}
+
+/// Ports of the running recording proxies, by service.
+fn find_proxy_ports(project_location: &str, def: &Definition) -> HashMap<String, u16> {
+    def.services.iter()
+        .filter_map(|service| find_proxy_port(project_location, &service.name).map(|port| (service.name.clone(), port)))
+        .collect()
+}
+
+/// Starts a proxy in front of every service with `record`, `faults` or `intercept` set. The faults
+/// of `minitel.local.yaml` replace those set with `minitel fault set`.
+///
+/// Cluster services are proxied through a tunnel to their Service, so that the proxy sees the
+/// same requests as from within the cluster. Intercepted services are proxied through a tunnel to
+/// their origin Service instead, as their Service fronts the proxy itself.
+fn configure_proxies(project_location: &str, kube: &KubeTarget, def: &Definition, cfg: &HashMap<String, ServiceConfig>) {
+    stop_all_proxies(project_location);
+
+    for service in &def.services {
+        let service_config = match cfg.get(&service.name) {
+            Some(c) if c.record || c.faults.is_some() || c.intercept.is_some() => c,
+            _ => continue
+        };
+
+        let faults = service_config.faults.clone().unwrap_or_default();
+        validate_faults(&faults).unwrap_or_else(|e| panic!("Invalid faults for {}: {}", service.name, e));
+        let faults_file = get_faults_file(project_location, &service.name);
+        write_faults(&faults_file, &faults);
+
+        let interception = service_config.intercept.as_ref().map(|rules| {
+            if service_config.deploy.is_on_host() {
+                panic!("Service {} runs on the host, only cluster services can be intercepted", service.name);
+            }
+            if rules.headers.is_empty() && rules.paths.is_empty() {
+                panic!("Interception of {} needs headers or paths to match", service.name);
+            }
+            Interception {
+                upstream: format!("localhost:{}", service.port),
+                rules: rules.clone()
+            }
+        });
+
+        let upstream_port = if interception.is_some() {
+            let origin = create_origin_service_name(&service.name);
+            i32::from(start_tunnel_to(project_location, kube, &origin, &format!("service/{}", origin), &[SERVICE_PORT])[0])
+        } else if service_config.deploy.is_on_host() {
+            service.port
+        } else {
+            let ports = find_tunnel_ports(project_location, &service.name)
+                .unwrap_or_else(|| start_tunnel(project_location, kube, &service.name, &[SERVICE_PORT]));
+            i32::from(ports[0])
+        };
+
+        start_proxy(project_location, &service.name, &format!("localhost:{}", upstream_port), &faults_file, service_config.record, interception.as_ref());
+        if service_config.record {
+            println!("Recording traffic of {} to {}", service.name, get_recording_file(project_location, &service.name).display());
+        }
+        if service_config.faults.is_some() {
+            println!("Injecting faults into traffic of {}", service.name);
+        }
+        if interception.is_some() {
+            println!("Intercepting matching requests of {} to localhost:{}", service.name, service.port);
+        }
+    }
+}
+
+fn run_up(no_prune: bool, force_context: bool) {
+    deploy(&load_project(force_context), no_prune);
+}
+
+/// Brings the whole app up to date in the cluster and on the host.
+fn deploy(project: &Project, no_prune: bool) {
+    let Project { location: project_location, def, cfg, kube, .. } = project;
+    let client = KubeClient::new(kube);
+    let cluster = RecordingCluster::new(&client);
+
+    create_namespace(&cluster, kube, def);
+    configure_tunnels(project_location, kube, def, cfg);
+    configure_proxies(project_location, kube, def, cfg);
+    let config_hash = configure_routing(&cluster, def, cfg, &find_proxy_ports(project_location, def));
+    if let Some(secret) = render_tls_secret(project_location, def) {
+        cluster.apply(&secret);
+    }
+    let dev_env = build_development_env(project_location, def, cfg);
+    let config = project.global_config(&cluster, &config_hash);
+
+    let default_cfg = ServiceConfig::default();
+
+    if let Ok(false) = check_etc_hosts(config.minikube_ip, def) {
+        run_patch_hosts_as_sudo(config.minikube_ip);
+    }
+
+    run_jobs(&config, def, cfg, &dev_env, |j| is_hook(j, Hook::PreUp));
+
+    for service in &def.services {
+        run_jobs(&config, def, cfg, &dev_env, |j| j.before.as_ref() == Some(&service.name));
+        start_service(&config, service, cfg.get(&service.name).get_or_insert(&default_cfg), &dev_env);
+    }
+
+    validate_and_apply_extras(&config, def, cfg);
+
+    run_jobs(&config, def, cfg, &dev_env, |j| is_hook(j, Hook::PostUp));
+
+    if !no_prune {
+        prune(&client, &def.app_name, &cluster.applied());
+    }
+
+    configure_debuggers(project_location, kube, def, cfg);
+}
+
+/// Forwards the debug port of the cluster services with `debug` set, and prints where to attach.
+///
+/// The tunnel goes to the Deployment rather than the Service, whose ports don't include the debug port.
+fn configure_debuggers(project_location: &str, kube: &KubeTarget, def: &Definition, cfg: &HashMap<String, ServiceConfig>) {
+    for service in &def.services {
+        let service_config = match cfg.get(&service.name) {
+            Some(c) => c,
+            None => continue
+        };
+        let debug = match &service_config.debug {
+            Some(debug) => debug,
+            None => continue
+        };
+
+        match service_config.deploy {
+            DeployMode::Cluster | DeployMode::Sync => {
+                let ports = start_tunnel_to(
+                    project_location, kube, &format!("{}-debug", service.name),
+                    &format!("deployment/{}", service.name), &[debug.port]
+                );
+                println!("Debugger of {} on localhost:{}", service.name, ports[0]);
+            }
+            DeployMode::Local => println!("Debugger of {} on localhost:{}", service.name, debug.port),
+            DeployMode::Docker => println!("[WARNING] Debugging {} is not supported in docker mode", service.name),
+            DeployMode::Mock => println!("[WARNING] Debugging {} is not supported in mock mode", service.name)
+        }
+    }
+}
+
+/// Finds the hash of a live routing ConfigMap holding the same routes as the current configuration.
+fn find_live_config_hash(cluster: &dyn ClusterApi, def: &Definition, cfg: &HashMap<String, ServiceConfig>, proxy_ports: &HashMap<String, u16>) -> Option<String> {
+    let prefix = create_config_map_ref(&def.app_name, "");
+
+    cluster.list(&create_app_selector(&def.app_name)).into_iter()
+        .filter(|object| object.kind == "ConfigMap" && object.name.starts_with(&prefix))
+        .find_map(|object| {
+            let hash = &object.name[prefix.len()..];
+            let live = cluster.get(&object)?;
+            let (_, desired) = parse_manifests(&build_routing_definition(def, cfg, proxy_ports, hash)).pop()?;
+            diff_object(&desired, &live).is_empty().then(|| String::from(hash))
+        })
+}
+
+fn run_diff(force_context: bool) {
+    let project = load_project(force_context);
+    let Project { location: project_location, def, cfg, kube, .. } = &project;
+    let cluster = KubeClient::new(kube);
+
+    let proxy_ports = find_proxy_ports(project_location, def);
+    // Reuse the live routing when it is unchanged, so that it doesn't show up in every diff
+    let config_hash = find_live_config_hash(&cluster, def, cfg, &proxy_ports).unwrap_or_else(create_config_hash);
+    let config = project.global_config(&cluster, &config_hash);
+    let default_cfg = ServiceConfig::default();
+
+    let mut manifests = vec!(build_routing_definition(def, cfg, &proxy_ports, &config_hash));
+    // Like up, leave alone a namespace minitel did not create
+    if cluster.get(&create_namespace_ref(&kube.namespace)).is_none_or(|live| is_owned_namespace(&live, &def.app_name)) {
+        manifests.insert(0, build_namespace_definition(&def.app_name, &kube.namespace));
+    }
+    for service in &def.services {
+        let service_config = cfg.get(&service.name).unwrap_or(&default_cfg);
+        if !service_config.deploy.is_on_host() {
+            for volume in service.volumes.iter().flatten() {
+                manifests.push(build_volume_claim_definition(&def.app_name, &service.name, volume, is_volume_seeded(&cluster, service, volume)));
+            }
+        }
+        manifests.push(render_service(&config, service, service_config));
+    }
+    manifests.extend(render_tls_secret(project_location, def));
+    manifests.extend(load_extras(&config, def, cfg, true));
+
+    let desired: Vec<(ObjectRef, serde_json::Value)> = manifests.iter()
+        .flat_map(|m| parse_manifests(m))
+        .map(|(object, value)| (object.relative_to(&kube.namespace), value))
+        .collect();
+    let mut unchanged = 0;
+
+    for (object, value) in &desired {
+        match cluster.get(object) {
+            None => println!("+ {} (new)", object),
+            Some(live) => {
+                let changes = diff_object(value, &live);
+                if changes.is_empty() {
+                    unchanged += 1;
+                } else {
+                    println!("~ {}", object);
+                    for change in changes {
+                        println!("    {}", change);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut desired_refs: Vec<ObjectRef> = desired.into_iter().map(|(object, _)| object).collect();
+    // Jobs of the up hooks are re-created by up rather than patched, they are not diffed
+    for job in &def.jobs {
+        let runs_on_up = job.before.is_some() || is_hook(job, Hook::PreUp) || is_hook(job, Hook::PostUp);
+        if runs_on_up && !cfg.get(&job.service).is_some_and(|s| s.deploy.is_on_host()) {
+            desired_refs.push(ObjectRef {
+                api_version: String::from("batch/v1"),
+                kind: String::from("Job"),
+                name: create_job_name(&job.name),
+                namespace: None
+            });
+        }
+    }
+    for object in find_prunable(&cluster, &def.app_name, &desired_refs) {
+        println!("- {} (pruned)", object);
+    }
+
+    println!("{} objects unchanged", unchanged);
+}
+
+/// Writes the environment as a bundle which can be deployed without minitel.
+///
+/// Sync services are exported in cluster mode, their source mount only exists under minitel.
+fn run_export(format: ExportFormat, output: Option<&str>) {
+    let project = load_offline_project();
+    let Project { location: project_location, def, cfg, kube, .. } = &project;
+    let default_cfg = ServiceConfig::default();
+
+    let config_hash = String::from("export");
+    let config = project.global_config(&OfflineCluster, &config_hash);
+
+    let mut manifests = vec!(
+        build_namespace_definition(&def.app_name, &kube.namespace),
+        // Proxies are local to the developer, and never exported
+        build_routing_definition(def, cfg, &HashMap::new(), &config_hash)
+    );
+    let mut images = Vec::new();
+    for service in &def.services {
+        let service_config = cfg.get(&service.name).unwrap_or(&default_cfg);
+        if service_config.deploy.is_on_host() {
+            manifests.push(render_service(&config, service, service_config));
+            continue;
+        }
+        for volume in service.volumes.iter().flatten() {
+            manifests.push(build_volume_claim_definition(&def.app_name, &service.name, volume, false));
+        }
+        manifests.push(build_service_cluster_definition(&build_k8s_service_config(&config, service, service_config)));
+        images.push((service.name.clone(), create_image_name(&def.app_name, &service.name)));
+    }
+    // The development certificate is only trusted on this machine, and its key must not leave it
+    manifests.extend(load_extras(&config, def, cfg, false));
+    if def.tls {
+        println!("[WARNING] TLS is not exported, add a certificate for {} to the Ingresses of the bundle", def.hostname);
+    }
+
+    let export = ExportConfig {
+        app_name: &def.app_name,
+        namespace: &kube.namespace,
+        hostname: &def.hostname,
+        images
     };
+    let dir = output.map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(project_location).join(".minitel/export"));
+
+    match format {
+        ExportFormat::Yaml => write_yaml_bundle(&dir, &export, &manifests),
+        ExportFormat::Helm => write_helm_chart(&dir, &export, &manifests),
+        ExportFormat::Kustomize => write_kustomization(&dir, &export, &manifests)
+    }
+    println!("Exported {} to {}", def.app_name, dir.display());
+}
+
+fn run_run(job_name: &str, force_context: bool) -> Result<(), io::Error> {
+    let project = load_project(force_context);
+    let Project { location: project_location, def, cfg, kube, .. } = &project;
+
+    let job = def.jobs.iter().find(|j| j.name == job_name)
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("No job named {}", job_name)))?;
+
+    let cluster = KubeClient::new(kube);
+
+    create_namespace(&cluster, kube, def);
+    let proxy_ports = find_proxy_ports(project_location, def);
+    // Reuse the routing of `up` when it is current, otherwise apply one for this run only
+    let live_hash = find_live_config_hash(&cluster, def, cfg, &proxy_ports);
+    let config_hash = live_hash.clone().unwrap_or_else(|| configure_routing(&cluster, def, cfg, &proxy_ports));
+    let dev_env = build_development_env(project_location, def, cfg);
+    let config = project.global_config(&cluster, &config_hash);
+
+    let succeeded = run_job(&config, def, cfg, job, &dev_env);
+
+    if live_hash.is_none() {
+        cluster.delete(&ObjectRef {
+            api_version: String::from("v1"),
+            kind: String::from("ConfigMap"),
+            name: create_config_map_ref(&def.app_name, &config_hash),
+            namespace: None
+        });
+    }
+
+    if !succeeded {
+        return Err(io::Error::other(format!("Job {} failed", job_name)));
+    }
+
+    Ok(())
+}
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(800);
+
+struct WatchedService<'a> {
+    service: &'a Service,
+    config: &'a ServiceConfig,
+    watcher: Watcher,
+    last_change: Option<Instant>,
+    status: String
+}
 
-    if let Ok(false) = check_etc_hosts(&config, &def) {
-        run_patch_hosts_as_sudo();
+fn print_dev_status(watched: &[WatchedService]) {
+    let status: Vec<String> = watched.iter()
+        .map(|w| format!("{}: {}", w.service.name, w.status))
+        .collect();
+    print!("\r\x1b[K[minitel dev] {}", status.join(" | "));
+    io::stdout().flush().unwrap();
+}
+
+fn reload_service(cluster: &dyn ClusterApi, project_location: &str, app_name: &str, service: &Service, config: &ServiceConfig, env: &HashMap<String, String>) {
+    match config.deploy {
+        DeployMode::Cluster => {
+            build_service_image(project_location, app_name, &service.name);
+            cluster.restart_rollout(&service.name);
+        },
+        DeployMode::Local => {
+            stop_development_service(project_location, service, env);
+            start_development_service(project_location, service, env, config.debug.as_ref());
+        },
+        // The source is mounted in the pod or container, and mocks are read on every request:
+        // there is nothing to reload
+        DeployMode::Sync | DeployMode::Docker | DeployMode::Mock => {}
     }
+}
+
+fn run_dev(no_prune: bool, force_context: bool) {
+    let project = load_project(force_context);
+    deploy(&project, no_prune);
+
+    let Project { location: project_location, def, cfg, kube, .. } = &project;
+    let dev_env = build_development_env(project_location, def, cfg);
+    let cluster = KubeClient::new(kube);
+    let default_cfg = ServiceConfig::default();
 
-    for service in def.services {
-        start_service(&config, &service, cfg.get(&service.name).get_or_insert(&default_cfg), &dev_env);
+    let mut watched: Vec<WatchedService> = def.services.iter()
+        .map(|service| (service, cfg.get(&service.name).unwrap_or(&default_cfg)))
+        .filter(|(_, config)| !matches!(config.deploy, DeployMode::Sync | DeployMode::Docker | DeployMode::Mock))
+        .map(|(service, config)| {
+            let directory = Path::new(project_location).join("services").join(&service.name);
+            let ignore = service.watch_ignore.as_deref().unwrap_or(&[]);
+            WatchedService {
+                service,
+                config,
+                watcher: Watcher::new(&directory, ignore),
+                last_change: None,
+                status: String::from("watching")
+            }
+        })
+        .collect();
+
+    println!("Watching for changes. Press Ctrl-C to stop.");
+
+    loop {
+        for i in 0..watched.len() {
+            if watched[i].watcher.poll() {
+                watched[i].last_change = Some(Instant::now());
+                watched[i].status = String::from("changed");
+            }
+
+            let settled = watched[i].last_change.is_some_and(|t| t.elapsed() >= WATCH_DEBOUNCE);
+            if settled {
+                watched[i].last_change = None;
+                watched[i].status = String::from("reloading");
+                print_dev_status(&watched);
+                println!();
+
+                reload_service(&cluster, project_location, &def.app_name, watched[i].service, watched[i].config, &dev_env);
+
+                // Ignore changes made by the reload itself (build artifacts, pid files...)
+                watched[i].watcher.poll();
+                watched[i].status = String::from("up to date");
+            }
+        }
+        print_dev_status(&watched);
+        thread::sleep(WATCH_INTERVAL);
     }
+}
+
+/// Prints where the development CA is, and how to trust it.
+fn run_ca() {
+    ensure_ca();
+    let path = get_ca_cert_path();
 
-    validate_and_apply_extras(&project_location);
+    println!("{}", path.display());
+    println!();
+    println!("To trust it:");
+    println!("  macOS:   sudo security add-trusted-cert -d -r trustRoot -k /Library/Keychains/System.keychain {}", path.display());
+    println!("  Debian:  sudo cp {} /usr/local/share/ca-certificates/minitel.crt && sudo update-ca-certificates", path.display());
+    println!("  Node.js: export NODE_EXTRA_CA_CERTS={}", path.display());
 }
 
-fn run_patch_hosts() {
+/// Serves the mocks of a service. Started in the background by `up` for services in mock mode.
+fn run_mock_server_command(service_name: &str) -> Result<(), io::Error> {
     let project_location = locate_project().expect("Could not find project root.");
     let def = load_definition(&project_location);
 
-    let config = GlobalConfig {
-        host_ip: &get_host_ip(),
-        minikube_ip: &get_minikube_ip(),
-        app_name: &def.app_name,
-        config_hash: "dont care",
-        project_location: &project_location
+    let service = def.services.iter().find(|s| s.name == service_name)
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("No service named {}", service_name)))?;
+
+    run_mock_server(&get_mocks_dir(&project_location, &service.name), service.port);
+
+    Ok(())
+}
+
+/// Sends the recorded requests of a service to it again, bypassing its recording proxy.
+fn run_replay(service_name: &str) -> Result<(), io::Error> {
+    let project_location = locate_project().expect("Could not find project root.");
+    let def = load_definition(&project_location);
+    let cfg = load_config(&project_location);
+
+    let service = def.services.iter().find(|s| s.name == service_name)
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("No service named {}", service_name)))?;
+    let base_url = create_local_address(&project_location, &def, &cfg, service).url();
+    let ca_cert = if def.tls { Some(get_ca_cert_path()) } else { None };
+
+    for entry in load_recording(&get_recording_file(&project_location, service_name)) {
+        let method = entry.pointer("/request/method").and_then(|m| m.as_str()).unwrap_or_default();
+        let url = entry.pointer("/request/url").and_then(|u| u.as_str()).unwrap_or_default();
+        let recorded = entry.pointer("/response/status").and_then(|s| s.as_u64()).unwrap_or_default();
+
+        match replay_entry(&base_url, &entry, ca_cert.as_deref()) {
+            Some(status) => println!("{} {} -> {} (recorded {})", method, url, status, recorded),
+            None => println!("{} {} -> failed (recorded {})", method, url, recorded)
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_rate(value: &str) -> Result<f64, io::Error> {
+    value.parse().map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("Invalid rate {}", value)))
+}
+
+/// Changes the faults injected by the running proxy of a service, until the next `minitel up`.
+fn run_fault(action: &str, service_name: &str, args: &[String]) -> Result<(), io::Error> {
+    let project_location = locate_project().expect("Could not find project root.");
+    let def = load_definition(&project_location);
+
+    if !def.services.iter().any(|s| s.name == service_name) {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("No service named {}", service_name)));
+    }
+    if find_proxy_port(&project_location, service_name).is_none() {
+        return Err(io::Error::new(ErrorKind::NotFound, format!(
+            "{} has no proxy. Add `faults: {{}}` to it in minitel.local.yaml and run minitel up.", service_name
+        )));
+    }
+
+    let faults_file = get_faults_file(&project_location, service_name);
+    let faults = match action {
+        "clear" => Faults::default(),
+        "set" => {
+            let mut faults = read_faults(&faults_file);
+            if let Some(latency) = find_flag_value(args, "--latency") {
+                faults.latency = Some(latency.to_string());
+            }
+            if let Some(rate) = find_flag_value(args, "--error-rate") {
+                faults.error_rate = parse_rate(rate)?;
+            }
+            if let Some(statuses) = find_flag_value(args, "--error-status") {
+                faults.error_status = statuses.split(',')
+                    .map(|status| status.trim().parse().map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("Invalid status {}", status))))
+                    .collect::<Result<_, _>>()?;
+            }
+            if let Some(rate) = find_flag_value(args, "--reset-rate") {
+                faults.reset_rate = parse_rate(rate)?;
+            }
+            if let Some(bandwidth) = find_flag_value(args, "--bandwidth") {
+                faults.bandwidth = Some(bandwidth.to_string());
+            }
+            faults
+        }
+        _ => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown fault action {}", action)))
+    };
+
+    validate_faults(&faults).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    write_faults(&faults_file, &faults);
+    println!("Faults of {}: {}", service_name, serde_json::to_string(&faults).unwrap());
+
+    Ok(())
+}
+
+/// Runs a command where a service runs: in a ready pod of cluster services, in the container of
+/// docker services, and in `services/<name>` with the environment of its scripts for local ones.
+fn run_exec(service_name: &str, command: &[String], force_context: bool) -> Result<(), io::Error> {
+    let project_location = locate_project().expect("Could not find project root.");
+    let def = load_definition(&project_location);
+    let cfg = load_config(&project_location);
+
+    let service = def.services.iter().find(|s| s.name == service_name)
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("No service named {}", service_name)))?;
+    let tty = io::stdin().is_terminal() && io::stdout().is_terminal();
+
+    let status = match cfg.get(service_name).map(|c| &c.deploy).unwrap_or(&DeployMode::Cluster) {
+        DeployMode::Local => {
+            let dev_env = build_development_env(&project_location, &def, &cfg);
+            exec_development_command(&project_location, service, command, &dev_env)
+        }
+        DeployMode::Docker => exec_service_container(&def.app_name, service_name, tty, command),
+        DeployMode::Mock => {
+            return Err(io::Error::new(ErrorKind::Unsupported, format!("{} is mocked, there is nothing to run commands in", service_name)));
+        }
+        DeployMode::Cluster | DeployMode::Sync => {
+            let kube = create_kube_target(&def, force_context);
+            let cluster = KubeClient::new(&kube);
+            let pod = cluster.find_ready_pod(&format!("app={},tier={}", def.app_name, service_name))
+                .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("No ready pod of {}", service_name)))?;
+            kubectl_exec_attached(&kube, &pod, tty, command)
+        }
+    };
+
+    // Passed on, so that minitel exec can be used in scripts
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+
+    Ok(())
+}
+
+/// Opens a shell where a service runs, the user's shell for local services.
+fn run_shell(service_name: &str, force_context: bool) -> Result<(), io::Error> {
+    let project_location = locate_project().expect("Could not find project root.");
+    let cfg = load_config(&project_location);
+
+    let shell = match cfg.get(service_name).map(|c| &c.deploy) {
+        Some(DeployMode::Local) => env::var("SHELL").unwrap_or_else(|_| String::from("sh")),
+        _ => String::from("sh")
     };
 
-    patch_etc_hosts(&config, &def).expect("Failed to update hosts file!");
+    run_exec(service_name, &[shell], force_context)
+}
+
+/// Maps the hostname to the given minikube address. Run as root, where the minikube profiles of the user are unknown.
+fn run_patch_hosts(minikube_ip: &str) {
+    let project_location = locate_project().expect("Could not find project root.");
+    let def = load_definition(&project_location);
+
+    patch_etc_hosts(minikube_ip, &def).expect("Failed to update hosts file!");
 }
 
 fn run_help() {
-    println!("usage: minitel <up | down>")
+    println!("usage: minitel <up [--no-prune] | dev [--no-prune] | diff | down [--purge] | run <job> | export [--format yaml|helm|kustomize] [--output <dir>] | exec <service> -- <command> | shell <service> | replay <service> | fault <set|clear> <service> [--latency <time>] [--error-rate <0-1>] [--error-status <codes>] [--reset-rate <0-1>] [--bandwidth <size>] | ca> [--force-context]")
+}
+
+/// Value following a flag, as in `--format helm`.
+fn find_flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(|v| v.as_str())
 }
 
 fn main() -> Result<(), io::Error> {
     let args: Vec<String> = env::args().collect();
+    // What follows `--` belongs to the command run by exec
+    let (options, command) = args.split_at(args.iter().position(|a| a == "--").unwrap_or(args.len()));
+    let force_context = options.iter().any(|a| a == "--force-context");
+    let args: Vec<String> = options.iter()
+        .filter(|a| *a != "--force-context")
+        .chain(command)
+        .cloned()
+        .collect();
     let cmd = args.get(1);
 
     if cmd.map_or_else(|| false, |v| v.eq("up")) {
-        run_up();
+        run_up(args.iter().any(|a| a == "--no-prune"), force_context);
+    } else if cmd.map_or_else(|| false, |v| v.eq("dev")) {
+        run_dev(args.iter().any(|a| a == "--no-prune"), force_context);
+    } else if cmd.map_or_else(|| false, |v| v.eq("diff")) {
+        run_diff(force_context);
     } else if cmd.map_or_else(|| false, |v| v.eq("down")) {
-        run_down();
-    } else if cmd.map_or_else(|| false, |v| v.eq("patch-hosts")) {
-        run_patch_hosts();
+        run_down(args.iter().any(|a| a == "--purge"), force_context);
+    } else if cmd.map_or_else(|| false, |v| v.eq("run")) && args.len() >= 3 {
+        run_run(&args[2], force_context)?;
+    } else if cmd.map_or_else(|| false, |v| v.eq("export")) {
+        let format = find_flag_value(&args, "--format").unwrap_or("yaml");
+        match ExportFormat::parse(format) {
+            Some(format) => run_export(format, find_flag_value(&args, "--output")),
+            None => {
+                run_help();
+                return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown export format {}", format)))
+            }
+        }
+    } else if cmd.map_or_else(|| false, |v| v.eq("mock-server")) && args.len() >= 3 {
+        run_mock_server_command(&args[2])?;
+    } else if cmd.map_or_else(|| false, |v| v.eq("replay")) && args.len() >= 3 {
+        run_replay(&args[2])?;
+    } else if cmd.map_or_else(|| false, |v| v.eq("fault")) && args.len() >= 4 {
+        run_fault(&args[2], &args[3], &args[4..])?;
+    } else if cmd.map_or_else(|| false, |v| v.eq("proxy")) && args.len() >= 5 {
+        let port = args[2].parse().map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Invalid proxy port"))?;
+        run_proxy(
+            port, &args[3], Path::new(&args[4]),
+            find_flag_value(&args, "--record").map(Path::new),
+            find_flag_value(&args, "--intercept").map(Path::new)
+        );
+    } else if cmd.map_or_else(|| false, |v| v.eq("ca")) {
+        run_ca();
+    } else if cmd.map_or_else(|| false, |v| v.eq("exec")) && args.len() >= 4 {
+        let command = match args.iter().position(|a| a == "--") {
+            Some(i) => &args[i + 1..],
+            None => &args[3..]
+        };
+        if command.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "No command to run"));
+        }
+        run_exec(&args[2], command, force_context)?;
+    } else if cmd.map_or_else(|| false, |v| v.eq("shell")) && args.len() >= 3 {
+        run_shell(&args[2], force_context)?;
+    } else if cmd.map_or_else(|| false, |v| v.eq("patch-hosts")) && args.len() >= 3 {
+        run_patch_hosts(&args[2]);
     } else {
         run_help();
         return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid arguments"))
@@ -221,3 +1454,256 @@ fn main() -> Result<(), io::Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cluster::MemoryCluster;
+
+    fn create_object(kind: &str, name: &str, app_name: &str) -> String {
+        format!("apiVersion: v1\nkind: {}\nmetadata:\n  name: {}\n  labels:\n    minitel-app: {}\n", kind, name, app_name)
+    }
+
+    fn create_cluster(app_name: &str) -> MemoryCluster {
+        let cluster = MemoryCluster::default();
+        for (kind, name) in [("Service", "api"), ("Endpoints", "api"), ("ConfigMap", "old"), ("PersistentVolumeClaim", "data")] {
+            cluster.apply(&create_object(kind, name, app_name));
+        }
+        cluster.apply(&create_object("ConfigMap", "other", "other-app"));
+        cluster
+    }
+
+    fn create_target(namespace: &str) -> KubeTarget {
+        KubeTarget { context: "minikube".to_string(), namespace: namespace.to_string() }
+    }
+
+    fn create_global_config<'a>(kube: &'a KubeTarget, cluster: &'a dyn ClusterApi) -> GlobalConfig<'a> {
+        GlobalConfig {
+            host_ip: "192.168.49.1",
+            minikube_ip: "192.168.49.2",
+            app_name: "shop",
+            hostname: "shop.test",
+            config_hash: "1234",
+            project_location: ".",
+            kube,
+            cluster
+        }
+    }
+
+    #[test]
+    fn find_prunable_keeps_desired_objects_claims_and_endpoints() {
+        let cluster = create_cluster("shop");
+        let desired = cluster.apply(&create_object("Service", "api", "shop"));
+
+        let prunable = find_prunable(&cluster, "shop", &desired);
+
+        assert_eq!(prunable.iter().map(|o| (o.kind.as_str(), o.name.as_str())).collect::<Vec<_>>(), vec![("ConfigMap", "old")]);
+    }
+
+    #[test]
+    fn find_prunable_prunes_endpoints_of_removed_services() {
+        let cluster = create_cluster("shop");
+
+        let mut prunable: Vec<_> = find_prunable(&cluster, "shop", &[]).into_iter().map(|o| o.kind).collect();
+        prunable.sort();
+
+        assert_eq!(prunable, vec!["ConfigMap", "Endpoints", "Service"]);
+    }
+
+    #[test]
+    fn find_prunable_tells_apart_objects_of_other_namespaces() {
+        let cluster = MemoryCluster::default();
+        let in_monitoring = "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: settings\n  namespace: monitoring\n  labels:\n    minitel-app: shop\n";
+        let desired = cluster.apply(in_monitoring);
+        cluster.apply(&create_object("ConfigMap", "settings", "shop"));
+        cluster.apply(&create_object("Namespace", "monitoring", "shop"));
+
+        let prunable = find_prunable(&cluster, "shop", &desired);
+
+        assert_eq!(prunable.len(), 1);
+        assert_eq!(prunable[0].to_string(), "configmap/settings");
+        assert_eq!(desired[0].to_string(), "configmap/settings in namespace monitoring");
+    }
+
+    #[test]
+    fn find_prunable_prunes_cluster_scoped_chart_objects() {
+        let cluster = create_cluster("shop");
+        let rendered = "\
+apiVersion: rbac.authorization.k8s.io/v1
+kind: ClusterRole
+metadata:
+  name: operator
+---
+apiVersion: rbac.authorization.k8s.io/v1
+kind: ClusterRoleBinding
+metadata:
+  name: operator
+";
+        for document in ensure_tag_config(rendered, "shop").unwrap() {
+            cluster.apply(&serde_yaml::to_string(&document).unwrap());
+        }
+        let desired = cluster.apply(&create_object("ConfigMap", "old", "shop"));
+
+        let mut prunable: Vec<_> = find_prunable(&cluster, "shop", &desired).into_iter().map(|o| o.kind).collect();
+        prunable.sort();
+
+        assert_eq!(prunable, vec!["ClusterRole", "ClusterRoleBinding", "Endpoints", "Service"]);
+    }
+
+    #[test]
+    fn delete_all_keeps_volume_claims_and_namespace_without_purge() {
+        let cluster = create_cluster("shop");
+        cluster.apply(&build_namespace_definition("shop", "shop"));
+
+        delete_all(&cluster, &create_target("shop"), "shop", false);
+
+        assert!(!cluster.contains("Service", "api"));
+        assert!(!cluster.contains("ConfigMap", "old"));
+        assert!(cluster.contains("PersistentVolumeClaim", "data"));
+        assert!(cluster.contains("Namespace", "shop"));
+        assert!(cluster.contains("ConfigMap", "other"));
+    }
+
+    #[test]
+    fn delete_all_purges_volume_claims_and_owned_namespace() {
+        let cluster = create_cluster("shop");
+        cluster.apply(&build_namespace_definition("shop", "shop"));
+
+        delete_all(&cluster, &create_target("shop"), "shop", true);
+
+        assert!(!cluster.contains("PersistentVolumeClaim", "data"));
+        assert!(!cluster.contains("Namespace", "shop"));
+        assert!(cluster.contains("ConfigMap", "other"));
+    }
+
+    #[test]
+    fn delete_all_deletes_cluster_scoped_objects_and_objects_of_other_namespaces() {
+        let cluster = create_cluster("shop");
+        cluster.apply("apiVersion: rbac.authorization.k8s.io/v1\nkind: ClusterRole\nmetadata:\n  name: shop-reader\n  labels:\n    minitel-app: shop\n");
+        cluster.apply("apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: dashboards\n  namespace: monitoring\n  labels:\n    minitel-app: shop\n");
+        cluster.apply(&create_object("Namespace", "monitoring", "shop"));
+
+        delete_all(&cluster, &create_target("shop"), "shop", false);
+
+        assert!(!cluster.contains("ClusterRole", "shop-reader"));
+        assert!(!cluster.contains("ConfigMap", "dashboards"));
+        assert!(cluster.contains("Namespace", "monitoring"));
+
+        delete_all(&cluster, &create_target("shop"), "shop", true);
+
+        assert!(!cluster.contains("Namespace", "monitoring"));
+    }
+
+    #[test]
+    fn delete_all_keeps_namespace_of_another_owner() {
+        let cluster = create_cluster("shop");
+        cluster.apply("apiVersion: v1\nkind: Namespace\nmetadata:\n  name: default\n");
+        cluster.apply(&build_namespace_definition("other-app", "shared"));
+
+        delete_all(&cluster, &create_target("default"), "shop", true);
+        delete_all(&cluster, &create_target("shared"), "shop", true);
+
+        assert!(cluster.contains("Namespace", "default"));
+        assert!(cluster.contains("Namespace", "shared"));
+    }
+
+    #[test]
+    fn apply_volumes_returns_volumes_until_their_seed_completes() {
+        let cluster = MemoryCluster::default();
+        let kube = create_target("shop");
+        let global_config = create_global_config(&kube, &cluster);
+        let service: Service = serde_yaml::from_str("
+            name: db
+            port: 5432
+            volumes:
+              - name: data
+                path: /var/lib/postgresql/data
+                seed:
+                  command: [psql, -f, /seed.sql]
+              - name: cache
+                path: /var/cache
+        ").unwrap();
+
+        let names = |volumes: Vec<&Volume>| volumes.iter().map(|v| v.name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(names(apply_volumes(&global_config, &service)), vec!["data"]);
+        // The seed failed before it marked the claim
+        assert_eq!(names(apply_volumes(&global_config, &service)), vec!["data"]);
+        assert!(cluster.contains("PersistentVolumeClaim", "db-cache"));
+
+        let data = &service.volumes.as_ref().unwrap()[0];
+        cluster.apply(&build_volume_claim_definition("shop", "db", data, true));
+
+        assert!(apply_volumes(&global_config, &service).is_empty());
+        assert!(is_volume_seeded(&cluster, &service, data));
+    }
+
+    #[test]
+    fn validate_context_allows_local_clusters_and_allowed_contexts() {
+        let def: Definition = serde_yaml::from_str("
+            app-name: shop
+            hostname: shop.test
+            allowed-contexts: [staging, dev-*]
+            services: []
+        ").unwrap();
+        let existing: Vec<String> = vec!("minikube", "docker-desktop", "kind-shop", "staging", "dev-eu", "production")
+            .into_iter().map(String::from).collect();
+
+        for context in ["minikube", "docker-desktop", "kind-shop", "staging", "dev-eu"] {
+            assert_eq!(validate_context(&def, context, &existing, false), Ok(()));
+        }
+        assert!(validate_context(&def, "production", &existing, false).unwrap_err().contains("--force-context"));
+        assert_eq!(validate_context(&def, "production", &existing, true), Ok(()));
+        assert_eq!(validate_context(&def, "missing", &existing, true), Err(String::from("Kube context missing does not exist.")));
+    }
+
+    #[test]
+    fn validate_context_only_allows_local_clusters_by_default() {
+        let def: Definition = serde_yaml::from_str("
+            app-name: shop
+            hostname: shop.test
+            services: []
+        ").unwrap();
+        let existing: Vec<String> = vec!("colima", "k3d-shop", "staging").into_iter().map(String::from).collect();
+
+        assert_eq!(validate_context(&def, "colima", &existing, false), Ok(()));
+        assert_eq!(validate_context(&def, "k3d-shop", &existing, false), Ok(()));
+        assert!(validate_context(&def, "staging", &existing, false).is_err());
+    }
+
+    #[test]
+    fn service_settings_are_overridden_by_the_local_config() {
+        let cluster = MemoryCluster::default();
+        let kube = create_target("shop");
+        let global_config = create_global_config(&kube, &cluster);
+        let service: Service = serde_yaml::from_str("
+            name: backend
+            port: 8000
+            replicas: 2
+            resources:
+              requests:
+                cpu: 100m
+                memory: 128Mi
+              limits:
+                memory: 256Mi
+            command: [node]
+            args: [server.js]
+            labels:
+              team: checkout
+            annotations:
+              prometheus.io/scrape: 'true'
+        ").unwrap();
+        let cfg: HashMap<String, ServiceConfig> = serde_yaml::from_str("
+            backend:
+              replicas: 1
+              args: [server.js, --verbose]
+              labels:
+                team: payments
+                tier: overridden
+        ").unwrap();
+
+        let config = build_k8s_service_config(&global_config, &service, &cfg["backend"]);
+
+        k8s::tests::assert_golden("service_cluster_overrides", &build_service_cluster_definition(&config));
+    }
+}
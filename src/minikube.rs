@@ -1,11 +1,15 @@
 
+use crate::background;
+
 use std::process::Command;
 use std::str;
 
-pub fn get_host_ip() -> String {
+/// Address of the host as seen from the minikube node of `profile`.
+pub fn get_host_ip(profile: &str) -> String {
     let output = Command::new("sh")
         .arg("-c")
-        .arg("minikube ssh cat /etc/hosts | grep host.minikube.internal | cut -f1")
+        .arg("minikube -p \"$0\" ssh cat /etc/hosts | grep host.minikube.internal | cut -f1")
+        .arg(profile)
         .output()
         .expect("Failed to execute command");
 
@@ -17,11 +21,13 @@ pub fn get_host_ip() -> String {
         panic!("Could not obtain host ip address. Is minikube running?");
     }
 
-    return String::from(result.trim());
+    String::from(result.trim())
 }
 
-pub fn get_minikube_ip() -> String {
+pub fn get_minikube_ip(profile: &str) -> String {
     let output = Command::new("minikube")
+        .arg("-p")
+        .arg(profile)
         .arg("ip")
         .output()
         .expect("Failed to execute command");
@@ -34,5 +40,43 @@ pub fn get_minikube_ip() -> String {
         panic!("Could not obtain host ip address. Is minikube running?");
     }
 
-    return String::from(result.trim());
-}
\ No newline at end of file
+    String::from(result.trim())
+}
+
+/// Name of the active minikube profile, which is also the name of its kube context.
+pub fn get_minikube_profile() -> String {
+    let output = Command::new("minikube")
+        .arg("profile")
+        .output()
+        .expect("Failed to execute command");
+
+    let result = str::from_utf8(output.stdout.as_slice()).expect("");
+
+    if !output.status.success() || result.trim().is_empty() {
+        panic!("Could not obtain minikube profile. Is minikube installed?");
+    }
+
+    String::from(result.trim())
+}
+
+/// Path on the minikube node where the source of a service gets mounted.
+pub fn get_mount_node_path(app_name: &str, service_name: &str) -> String {
+    format!("/minitel/{}/{}", app_name, service_name)
+}
+
+/// Starts a background `minikube mount` sharing `source` at `target` on the node of `profile`.
+pub fn start_mount(project_location: &str, profile: &str, name: &str, source: &str, target: &str) {
+    let script = "minikube -p \"$0\" mount \"$1:$2\"";
+
+    if !background::spawn(project_location, name, "mount", script, [profile, source, target], None) {
+        panic!("Could not mount {} into minikube", source);
+    }
+}
+
+pub fn stop_mount(project_location: &str, name: &str) {
+    background::stop(project_location, name, "mount");
+}
+
+pub fn stop_all_mounts(project_location: &str) {
+    background::stop_all(project_location, "mount");
+}
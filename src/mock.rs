@@ -0,0 +1,227 @@
+use crate::background;
+use crate::http::read_request;
+
+use std::env;
+use std::fs;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Path;
+use std::path::PathBuf;
+use serde_json::json;
+use serde_json::Value;
+
+/// Directory holding the fixtures of a service, and optionally its `openapi.yaml` or `openapi.json`.
+pub fn get_mocks_dir(project_location: &str, service_name: &str) -> PathBuf {
+    Path::new(project_location).join("services").join(service_name).join("mocks")
+}
+
+/// Starts `minitel mock-server` in the background for a service, logging to `.minitel/run`.
+pub fn start_mock_server(project_location: &str, service_name: &str) {
+    let program = env::current_exe().expect("Could not locate the minitel executable");
+    let log_file = background::get_run_file(project_location, service_name, "mock", "log");
+    let args = vec!(program.into_os_string(), service_name.into());
+
+    if !background::spawn(project_location, service_name, "mock", "\"$0\" mock-server \"$1\"", args, Some(&log_file)) {
+        panic!("Could not start mock server for {}", service_name);
+    }
+}
+
+pub fn stop_mock_server(project_location: &str, service_name: &str) {
+    background::stop(project_location, service_name, "mock");
+}
+
+pub fn stop_all_mock_servers(project_location: &str) {
+    background::stop_all(project_location, "mock");
+}
+
+struct MockResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>
+}
+
+fn guess_content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => "application/json",
+        Some("html") => "text/html",
+        Some("txt") => "text/plain",
+        Some("xml") => "application/xml",
+        _ => "application/octet-stream"
+    }
+}
+
+/// Looks for a fixture of the request: `<path>.<method>.json`, `<path>.json`, `<path>` or `<path>/index.json`.
+fn find_fixture(dir: &Path, method: &str, path: &str) -> Option<MockResponse> {
+    let relative = path.trim_matches('/');
+    // Requests must not escape the mocks directory
+    if relative.split('/').any(|segment| segment == "..") {
+        return None;
+    }
+    let base = if relative.is_empty() { String::from("index") } else { String::from(relative) };
+
+    let candidates = vec!(
+        dir.join(format!("{}.{}.json", base, method.to_lowercase())),
+        dir.join(format!("{}.json", base)),
+        dir.join(&base),
+        dir.join(&base).join("index.json")
+    );
+
+    candidates.into_iter()
+        .find(|candidate| candidate.is_file())
+        .map(|file| MockResponse {
+            status: 200,
+            content_type: guess_content_type(&file),
+            body: fs::read(&file).unwrap_or_default()
+        })
+}
+
+fn load_openapi(dir: &Path) -> Option<Value> {
+    ["openapi.yaml", "openapi.yml", "openapi.json"].iter()
+        .map(|name| dir.join(name))
+        .find(|file| file.is_file())
+        .map(|file| {
+            let contents = fs::read_to_string(&file).unwrap();
+            serde_yaml::from_str(&contents).unwrap_or_else(|e| panic!("Invalid OpenAPI document {}: {}", file.display(), e))
+        })
+}
+
+/// True if a path matches an OpenAPI path template such as `/users/{id}`.
+fn matches_path_template(template: &str, path: &str) -> bool {
+    let template: Vec<&str> = template.trim_matches('/').split('/').collect();
+    let path: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    template.len() == path.len() && template.iter().zip(&path)
+        .all(|(t, p)| (t.starts_with('{') && t.ends_with('}')) || t == p)
+}
+
+/// Answers with the example of the first successful response of the matching operation.
+fn find_openapi_example(spec: &Value, method: &str, path: &str) -> Option<MockResponse> {
+    let operation = spec.get("paths")?.as_object()?.iter()
+        .find(|(template, _)| matches_path_template(template, path))
+        .and_then(|(_, item)| item.get(method.to_lowercase()))?;
+
+    let (status, response) = operation.get("responses")?.as_object()?.iter()
+        .find(|(status, _)| status.starts_with('2'))?;
+    let content = response.get("content").and_then(|c| c.get("application/json"));
+    let example = content.and_then(|c| c.get("example").cloned())
+        .or_else(|| content.and_then(|c| c.get("examples"))
+            .and_then(Value::as_object)
+            .and_then(|examples| examples.values().next())
+            .and_then(|example| example.get("value").cloned()));
+
+    Some(MockResponse {
+        status: status.parse().unwrap_or(200),
+        content_type: "application/json",
+        body: example.map(|e| e.to_string().into_bytes()).unwrap_or_default()
+    })
+}
+
+fn write_response(stream: &mut TcpStream, response: &MockResponse) {
+    let head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, response.content_type, response.body.len()
+    );
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&response.body);
+}
+
+/// Serves the fixtures of `dir` on a port until killed. Fixtures win over the OpenAPI examples.
+pub fn run_mock_server(dir: &Path, port: i32) {
+    let listener = TcpListener::bind(("0.0.0.0", port as u16))
+        .unwrap_or_else(|e| panic!("Could not listen on port {}: {}", port, e));
+    println!("Serving mocks of {} on port {}", dir.display(), port);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        // The body is not used, but must be consumed before answering
+        let request = match read_request(&mut BufReader::new(&mut stream)) {
+            Some(request) => request,
+            None => continue
+        };
+        let (method, path) = (request.method(), request.path());
+
+        // Fixtures are read on every request, so that they can be edited while the server runs
+        let response = find_fixture(dir, method, path)
+            .or_else(|| load_openapi(dir).and_then(|spec| find_openapi_example(&spec, method, path)))
+            .unwrap_or_else(|| MockResponse {
+                status: 404,
+                content_type: "application/json",
+                body: json!({ "error": format!("No mock for {} {}", method, path) }).to_string().into_bytes()
+            });
+
+        println!("{} {} -> {}", method, path, response.status);
+        write_response(&mut stream, &response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Mocks directory holding `files`, unique to the test.
+    fn create_mocks_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("minitel-mocks-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, content) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn find_body(dir: &Path, method: &str, path: &str) -> Option<String> {
+        find_fixture(dir, method, path).map(|response| String::from_utf8(response.body).unwrap())
+    }
+
+    #[test]
+    fn find_fixture_prefers_the_method_specific_file() {
+        let dir = create_mocks_dir("method", &[("users.json", "list"), ("users.post.json", "created")]);
+
+        assert_eq!(find_body(&dir, "POST", "/users").as_deref(), Some("created"));
+        assert_eq!(find_body(&dir, "GET", "/users/").as_deref(), Some("list"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn find_fixture_falls_back_to_files_and_index() {
+        let dir = create_mocks_dir("index", &[("index.json", "root"), ("logo.txt", "logo"), ("orders/index.json", "orders")]);
+
+        assert_eq!(find_body(&dir, "GET", "/").as_deref(), Some("root"));
+        assert_eq!(find_fixture(&dir, "GET", "/logo.txt").unwrap().content_type, "text/plain");
+        assert_eq!(find_body(&dir, "GET", "/orders").as_deref(), Some("orders"));
+        assert!(find_fixture(&dir, "GET", "/missing").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn find_fixture_rejects_traversal() {
+        let dir = create_mocks_dir("traversal", &[("mocks/users.json", "users"), ("secret.json", "secret")]);
+        let mocks = dir.join("mocks");
+
+        assert!(find_fixture(&mocks, "GET", "/../secret").is_none());
+        assert!(find_fixture(&mocks, "GET", "/users/../../secret.json").is_none());
+        assert_eq!(find_body(&mocks, "GET", "/users").as_deref(), Some("users"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn matches_path_template_with_parameters() {
+        assert!(matches_path_template("/users/{id}", "/users/42"));
+        assert!(matches_path_template("/users/{id}/orders/{order}", "/users/42/orders/7/"));
+        assert!(matches_path_template("/health", "/health"));
+    }
+
+    #[test]
+    fn matches_path_template_rejects_other_paths() {
+        assert!(!matches_path_template("/users/{id}", "/users"));
+        assert!(!matches_path_template("/users/{id}", "/users/42/orders"));
+        assert!(!matches_path_template("/users/{id}", "/groups/42"));
+    }
+}
@@ -1,5 +1,9 @@
 
-use serde::{Deserialize};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use crate::k8s::KubeTarget;
+use crate::cluster::ClusterApi;
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "kebab-case")]
@@ -7,6 +11,58 @@ pub struct Definition {
     pub services: Vec<Service>,
     pub hostname: String,
     pub app_name: String,
+    /// Namespace the app is deployed to. Derived from the app name by default.
+    pub namespace: Option<String>,
+    /// Kube context to deploy to. Defaults to the one of the active minikube profile.
+    pub context: Option<String>,
+    /// Contexts minitel may run against, on top of the usual local cluster ones
+    #[serde(default)]
+    pub allowed_contexts: Vec<String>,
+    #[serde(default)]
+    pub jobs: Vec<Job>,
+    #[serde(default)]
+    pub charts: Vec<Chart>,
+    /// Name of the variables holding the address of a service, `{name}` standing for the
+    /// service name. Defaults to `{name}_SERVICE`.
+    pub env_name: Option<String>,
+    /// Serves the hostname over HTTPS, with a certificate of the development CA
+    #[serde(default)]
+    pub tls: bool,
+}
+
+/// Third party manifests rendered from a Helm chart or a Kustomize directory, relative to the project
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Chart {
+    pub name: String,
+    pub helm: Option<String>,
+    /// Values files passed to `helm template`
+    #[serde(default)]
+    pub values: Vec<String>,
+    pub kustomize: Option<String>,
+}
+
+/// A one-off command run with the image (or in the directory) of a service
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Job {
+    pub name: String,
+    pub service: String,
+    pub command: Vec<String>,
+    pub hook: Option<Hook>,
+    /// Runs the job during `up`, right before the given service is deployed
+    pub before: Option<String>,
+    /// Services whose rollout must complete before the job runs
+    #[serde(default)]
+    pub wait_for: Vec<String>
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Hook {
+    PreUp,
+    PostUp,
+    PreDown
 }
 
 #[derive(Deserialize, Debug)]
@@ -14,25 +70,162 @@ pub struct Definition {
 pub struct Service {
     pub name: String,
     pub port: i32,
-    pub cluster_prefix: Option<String>
+    /// Additional ports the service listens on, by name
+    pub ports: Option<BTreeMap<String, i32>>,
+    pub cluster_prefix: Option<String>,
+    /// Directory of the container where the source is mounted in sync mode
+    pub sync_path: Option<String>,
+    /// Glob patterns of files which do not trigger a reload in `minitel dev`
+    pub watch_ignore: Option<Vec<String>>,
+    pub volumes: Option<Vec<Volume>>,
+    pub replicas: Option<i32>,
+    pub resources: Option<Resources>,
+    pub command: Option<Vec<String>>,
+    pub args: Option<Vec<String>>,
+    pub labels: Option<HashMap<String, String>>,
+    pub annotations: Option<HashMap<String, String>>,
+    /// Local services reach this one through a port-forward rather than the ingress,
+    /// for services which don't speak HTTP such as databases
+    #[serde(default)]
+    pub tunnel: bool
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Resources {
+    pub requests: Option<ResourceQuantities>,
+    pub limits: Option<ResourceQuantities>
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResourceQuantities {
+    pub cpu: Option<String>,
+    pub memory: Option<String>
+}
+
+/// Persistent storage mounted into the pods of a service
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Volume {
+    pub name: String,
+    /// Mount path in the container
+    pub path: String,
+    pub size: Option<String>,
+    pub seed: Option<Seed>
+}
+
+/// Populates a volume once, `up` retries it until it succeeds
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Seed {
+    /// Directory, relative to the service directory, whose content is copied into the volume
+    pub files: Option<String>,
+    /// Command run in the service container once the files are copied
+    pub command: Option<Vec<String>>
 }
 
 pub struct GlobalConfig<'a> {
     pub host_ip: &'a str,
     pub minikube_ip: &'a str,
     pub app_name: &'a str,
+    pub hostname: &'a str,
     pub config_hash: &'a str,
-    pub project_location: &'a str
+    pub project_location: &'a str,
+    pub kube: &'a KubeTarget,
+    pub cluster: &'a dyn ClusterApi
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum DeployMode {
     Local,
-    Cluster
+    #[default]
+    Cluster,
+    /// Runs the cluster image with the local source mounted in the container
+    Sync,
+    /// Runs the cluster image in Docker on the host, with the local source mounted in the container
+    Docker,
+    /// Replaces the service with a stub server answering from the fixtures of `services/<name>/mocks`
+    Mock
 }
 
-#[derive(Deserialize, Debug)]
+impl DeployMode {
+    /// True when the service runs on the host rather than in the cluster.
+    pub fn is_on_host(&self) -> bool {
+        matches!(self, DeployMode::Local | DeployMode::Docker | DeployMode::Mock)
+    }
+}
+
+/// Per-developer settings from `minitel.local.yaml`.
+///
+/// Everything except `deploy` overrides the matching field of the `Service`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
 pub struct ServiceConfig {
-    pub deploy: DeployMode
-}
\ No newline at end of file
+    #[serde(default)]
+    pub deploy: DeployMode,
+    pub replicas: Option<i32>,
+    pub resources: Option<Resources>,
+    pub command: Option<Vec<String>>,
+    pub args: Option<Vec<String>>,
+    pub labels: Option<HashMap<String, String>>,
+    pub annotations: Option<HashMap<String, String>>,
+    /// Routes the traffic to the service through a proxy recording it to `.minitel/recordings`
+    #[serde(default)]
+    pub record: bool,
+    /// Routes the traffic to the service through a proxy injecting these faults
+    pub faults: Option<Faults>,
+    /// Sends the requests matching these rules to the local process, the others to the cluster
+    pub intercept: Option<Intercept>,
+    /// Enables the debugger of the service, in local mode and in the cluster
+    pub debug: Option<Debugger>
+}
+
+/// How to enable the debugger of a service, such as `NODE_OPTIONS: --inspect=0.0.0.0:9229`.
+///
+/// minitel forwards `port` of cluster services to localhost, local ones already listen on it.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Debugger {
+    /// Port the debugger listens on
+    pub port: i32,
+    /// Variables enabling the debugger
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Passed to `start.sh` in local mode, and appended to the `args` of the container in the
+    /// cluster, where they replace those of the image when the service has none
+    #[serde(default)]
+    pub args: Vec<String>
+}
+
+/// Requests to a cluster service answered by its local process instead, started with its
+/// `start.sh`. A request is intercepted when it carries all `headers` with their value and its
+/// path starts with one of `paths`, if any.
+///
+/// Only the main port is split, the named ports all reach the local process.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Intercept {
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub paths: Vec<String>
+}
+
+/// Faults injected by the proxy in front of a service. `minitel fault set` changes them at runtime.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Faults {
+    /// Delay added to every request, such as `500ms` or `2s`
+    pub latency: Option<String>,
+    /// Share of the requests, between 0 and 1, answered with an error instead of being forwarded
+    #[serde(default)]
+    pub error_rate: f64,
+    /// Status codes of the errors, picked at random; 500 when empty
+    #[serde(default)]
+    pub error_status: Vec<u16>,
+    /// Share of the connections, between 0 and 1, closed without a response
+    #[serde(default)]
+    pub reset_rate: f64,
+    /// Rate the responses are sent at, in bytes per second, such as `512`, `10kb` or `1mb`
+    pub bandwidth: Option<String>
+}
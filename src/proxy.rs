@@ -0,0 +1,418 @@
+use crate::fault::parse_bandwidth;
+use crate::fault::parse_latency;
+use crate::fault::pick_error_status;
+use crate::fault::read_faults;
+use crate::fault::roll;
+use crate::tunnel::allocate_port;
+use crate::background;
+use crate::http::HttpMessage;
+use crate::http::parse_response;
+use crate::http::read_request;
+use crate::Intercept;
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::str;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use socket2::SockRef;
+
+/// Headers which only concern a single connection, never forwarded nor replayed.
+const HOP_BY_HOP_HEADERS: [&str; 7] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "content-length", "te", "upgrade"];
+
+fn get_proxy_file(project_location: &str, service_name: &str, extension: &str) -> PathBuf {
+    background::get_run_file(project_location, service_name, "proxy", extension)
+}
+
+/// File the traffic of a service is recorded to, one HAR entry per line.
+pub fn get_recording_file(project_location: &str, service_name: &str) -> PathBuf {
+    Path::new(project_location).join(".minitel/recordings").join(format!("{}.har.jsonl", service_name))
+}
+
+/// Port of the proxy of a service, if it is running.
+pub fn find_proxy_port(project_location: &str, service_name: &str) -> Option<u16> {
+    if !background::is_running(project_location, service_name, "proxy") {
+        return None;
+    }
+    fs::read_to_string(get_proxy_file(project_location, service_name, "port")).ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Requests the proxy sends to another upstream than the service.
+#[derive(Deserialize, Serialize)]
+pub struct Interception {
+    pub upstream: String,
+    pub rules: Intercept
+}
+
+impl Interception {
+    fn matches(&self, request: &HttpMessage) -> bool {
+        let path = request.path();
+
+        self.rules.headers.iter().all(|(name, value)| request.header(name) == Some(value.as_str()))
+            && (self.rules.paths.is_empty() || self.rules.paths.iter().any(|prefix| path.starts_with(prefix.as_str())))
+    }
+}
+
+/// Starts `minitel proxy` in the background, forwarding to `upstream` (`host:port`), and returns its port.
+/// The faults of `faults_file` are injected, requests matching `interception` are diverted, and the
+/// traffic is recorded when `record` is set.
+pub fn start_proxy(project_location: &str, service_name: &str, upstream: &str, faults_file: &Path, record: bool, interception: Option<&Interception>) -> u16 {
+    let previous_port = fs::read_to_string(get_proxy_file(project_location, service_name, "port")).ok()
+        .and_then(|port| port.trim().parse().ok());
+    stop_proxy(project_location, service_name);
+    let (port, _) = allocate_port(previous_port);
+
+    let program = env::current_exe().expect("Could not locate the minitel executable");
+    let recording = get_recording_file(project_location, service_name);
+    fs::create_dir_all(recording.parent().unwrap()).expect("Could not create recordings directory");
+
+    let mut args: Vec<OsString> = vec!(program.into(), port.to_string().into(), upstream.into(), faults_file.into());
+    if record {
+        args.push("--record".into());
+        args.push(recording.into());
+    }
+    if let Some(interception) = interception {
+        let interception_file = get_proxy_file(project_location, service_name, "intercept");
+        fs::create_dir_all(interception_file.parent().unwrap()).expect("Could not create run directory");
+        fs::write(&interception_file, serde_json::to_string(interception).unwrap()).expect("Could not write interception file");
+        args.push("--intercept".into());
+        args.push(interception_file.into());
+    }
+
+    if !background::spawn(project_location, service_name, "proxy", "\"$0\" proxy \"$@\"", args, None) {
+        panic!("Could not start proxy for {}", service_name);
+    }
+
+    fs::write(get_proxy_file(project_location, service_name, "port"), port.to_string()).expect("Could not write port file");
+
+    port
+}
+
+/// Stops the proxy of a service. Its port is remembered for the next one.
+pub fn stop_proxy(project_location: &str, service_name: &str) {
+    background::stop(project_location, service_name, "proxy");
+}
+
+pub fn stop_all_proxies(project_location: &str) {
+    background::stop_all(project_location, "proxy");
+}
+
+fn forwarded_headers(message: &HttpMessage) -> impl Iterator<Item = &(String, String)> {
+    message.headers.iter().filter(|(name, _)| !HOP_BY_HOP_HEADERS.contains(&name.to_lowercase().as_str()))
+}
+
+fn build_har_headers(message: &HttpMessage) -> Value {
+    message.headers.iter().map(|(name, value)| json!({ "name": name, "value": value })).collect()
+}
+
+fn build_har_entry(request: &HttpMessage, response: &HttpMessage, started: SystemTime, elapsed_ms: u128) -> Value {
+    let mut request_line = request.start_line.splitn(3, ' ');
+    let method = request_line.next().unwrap_or_default();
+    let url = request_line.next().unwrap_or_default();
+    let http_version = request_line.next().unwrap_or_default();
+    let mut status_line = response.start_line.splitn(3, ' ');
+    let _ = status_line.next();
+    let status: u16 = status_line.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let status_text = status_line.next().unwrap_or_default();
+
+    json!({
+        "startedDateTime": started.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0),
+        "time": elapsed_ms,
+        "request": {
+            "method": method,
+            "url": url,
+            "httpVersion": http_version,
+            "headers": build_har_headers(request),
+            "postData": {
+                "mimeType": request.header("content-type").unwrap_or_default(),
+                "text": String::from_utf8_lossy(&request.body)
+            }
+        },
+        "response": {
+            "status": status,
+            "statusText": status_text,
+            "headers": build_har_headers(response),
+            "content": {
+                "size": response.body.len(),
+                "mimeType": response.header("content-type").unwrap_or_default(),
+                "text": String::from_utf8_lossy(&response.body)
+            }
+        }
+    })
+}
+
+/// Sends data to the client at most `bandwidth` bytes per second.
+fn write_throttled(client: &mut TcpStream, data: &[u8], bandwidth: Option<u64>) -> Option<()> {
+    let bandwidth = match bandwidth {
+        Some(bandwidth) => bandwidth,
+        None => return client.write_all(data).ok()
+    };
+
+    // Sent in slices every tenth of a second, so that the rate is smooth
+    let slice = ((bandwidth / 10) as usize).max(1);
+    for chunk in data.chunks(slice) {
+        let started = Instant::now();
+        client.write_all(chunk).ok()?;
+        let expected = Duration::from_secs_f64(chunk.len() as f64 / bandwidth as f64);
+        thread::sleep(expected.saturating_sub(started.elapsed()));
+    }
+
+    Some(())
+}
+
+fn build_error_response(status: u16, reason: &str, error: &str) -> Vec<u8> {
+    let body = json!({ "error": error }).to_string();
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body
+    ).into_bytes()
+}
+
+/// Answers with an error of the proxy itself, and returns it for the recording.
+fn send_error(client: &mut TcpStream, status: u16, reason: &str, error: &str, bandwidth: Option<u64>) -> Option<Vec<u8>> {
+    let raw = build_error_response(status, reason, error);
+    write_throttled(client, &raw, bandwidth)?;
+    Some(raw)
+}
+
+/// Sends the request to the upstream, and returns the connection the response comes back on.
+fn send_request(request: &HttpMessage, upstream: &str) -> Option<TcpStream> {
+    let mut head = format!("{}\r\n", request.start_line);
+    for (name, value) in forwarded_headers(request) {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", request.body.len()));
+
+    let mut server = TcpStream::connect(upstream).ok()?;
+    server.write_all(head.as_bytes()).ok()?;
+    server.write_all(&request.body).ok()?;
+    Some(server)
+}
+
+/// Passes the response on untouched as it arrives, until the upstream closes the connection, and
+/// returns it whole. Empty if the upstream sent nothing, none if the client went away.
+fn pipe_response(server: &mut TcpStream, client: &mut TcpStream, bandwidth: Option<u64>) -> Option<Vec<u8>> {
+    let mut raw = Vec::new();
+    let mut buffer = [0; 8192];
+
+    loop {
+        match server.read(&mut buffer) {
+            Ok(0) | Err(_) => return Some(raw),
+            Ok(read) => {
+                write_throttled(client, &buffer[..read], bandwidth)?;
+                raw.extend_from_slice(&buffer[..read]);
+            }
+        }
+    }
+}
+
+/// Settings of a running proxy, shared by the connections.
+struct ProxyConfig {
+    upstream: String,
+    faults_file: PathBuf,
+    interception: Option<Interception>,
+    recording: Option<Mutex<File>>
+}
+
+fn handle_connection(mut client: TcpStream, config: &ProxyConfig) -> Option<()> {
+    let request = read_request(&mut BufReader::new(&mut client))?;
+    let started = SystemTime::now();
+    let timer = Instant::now();
+
+    // Faults are read on every request, so that `minitel fault set` applies immediately
+    let faults = read_faults(&config.faults_file);
+    if let Some(latency) = faults.latency.as_deref().and_then(|l| parse_latency(l).ok()) {
+        thread::sleep(latency);
+    }
+    if roll(faults.reset_rate) {
+        // Without lingering, closing the connection sends a RST rather than a FIN
+        let _ = SockRef::from(&client).set_linger(Some(Duration::ZERO));
+        return None;
+    }
+
+    let upstream = config.interception.as_ref()
+        .filter(|interception| interception.matches(&request))
+        .map_or(config.upstream.as_str(), |interception| interception.upstream.as_str());
+    let bandwidth = faults.bandwidth.as_deref().and_then(|b| parse_bandwidth(b).ok());
+    let raw = if roll(faults.error_rate) {
+        send_error(&mut client, pick_error_status(&faults), "Injected Fault", "Fault injected by minitel", bandwidth)?
+    } else {
+        let raw = match send_request(&request, upstream) {
+            Some(mut server) => pipe_response(&mut server, &mut client, bandwidth)?,
+            None => Vec::new()
+        };
+        if raw.is_empty() {
+            send_error(&mut client, 502, "Bad Gateway", &format!("minitel could not reach {}", upstream), bandwidth)?
+        } else {
+            raw
+        }
+    };
+
+    let recording = config.recording.as_ref()?;
+    let response = parse_response(&raw)?;
+    let entry = build_har_entry(&request, &response, started, timer.elapsed().as_millis());
+    let mut file = recording.lock().unwrap();
+    writeln!(file, "{}", entry).ok()
+}
+
+/// Forwards every request to `upstream`, or to the upstream of the interception it matches,
+/// injecting the faults of `faults_file`, and appends the exchanges to `recording` if set, until killed.
+///
+/// Responses are passed on as they arrive, so that streams such as server-sent events flow, but
+/// are only recorded once complete. Bodies are recorded as text; binary bodies do not survive a replay.
+pub fn run_proxy(port: u16, upstream: &str, faults_file: &Path, recording: Option<&Path>, interception: Option<&Path>) {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .unwrap_or_else(|e| panic!("Could not listen on port {}: {}", port, e));
+    let config = Arc::new(ProxyConfig {
+        upstream: upstream.to_string(),
+        faults_file: faults_file.to_path_buf(),
+        interception: interception.map(|file| {
+            let contents = fs::read_to_string(file).unwrap_or_else(|e| panic!("Could not read {}: {}", file.display(), e));
+            serde_json::from_str(&contents).expect("Invalid interception file")
+        }),
+        recording: recording.map(|recording| {
+            let file = OpenOptions::new().create(true).append(true).open(recording)
+                .unwrap_or_else(|e| panic!("Could not open {}: {}", recording.display(), e));
+            Mutex::new(file)
+        })
+    });
+
+    for client in listener.incoming().map_while(Result::ok) {
+        let config = config.clone();
+        thread::spawn(move || handle_connection(client, &config));
+    }
+}
+
+/// Reads the entries of a recording.
+pub fn load_recording(recording: &Path) -> Vec<Value> {
+    fs::read_to_string(recording)
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", recording.display(), e))
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).expect("Invalid recording entry"))
+        .collect()
+}
+
+/// Sends the request of a recorded entry to `base_url` with curl, and returns the response status.
+pub fn replay_entry(base_url: &str, entry: &Value, ca_cert: Option<&Path>) -> Option<u16> {
+    let request = entry.get("request")?;
+    let method = request.get("method")?.as_str()?;
+    let url = format!("{}{}", base_url.trim_end_matches('/'), request.get("url")?.as_str()?);
+
+    let mut cmd = Command::new("curl");
+    cmd.arg("--silent")
+        .arg("--output").arg("/dev/null")
+        .arg("--write-out").arg("%{http_code}")
+        .arg("--request").arg(method);
+    for header in request.get("headers").and_then(Value::as_array).into_iter().flatten() {
+        let name = header.get("name").and_then(Value::as_str).unwrap_or_default();
+        let value = header.get("value").and_then(Value::as_str).unwrap_or_default();
+        if !HOP_BY_HOP_HEADERS.contains(&name.to_lowercase().as_str()) && !name.eq_ignore_ascii_case("host") {
+            cmd.arg("--header").arg(format!("{}: {}", name, value));
+        }
+    }
+    if let Some(ca_cert) = ca_cert {
+        cmd.arg("--cacert").arg(ca_cert);
+    }
+
+    let body = request.get("postData").and_then(|p| p.get("text")).and_then(Value::as_str).unwrap_or_default();
+    if !body.is_empty() {
+        cmd.arg("--data-binary").arg("@-");
+    }
+
+    let mut child = cmd.arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute curl. Is it installed?");
+    // curl doesn't read stdin without a body, failing to write it is fine
+    let _ = child.stdin.take().unwrap().write_all(body.as_bytes());
+
+    let output = child.wait_with_output().ok()?;
+    str::from_utf8(&output.stdout).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::write_faults;
+    use crate::Faults;
+    use std::io::ErrorKind;
+    use std::process;
+
+    fn create_interception(rules: &str) -> Interception {
+        Interception { upstream: String::from("localhost:8000"), rules: serde_yaml::from_str(rules).unwrap() }
+    }
+
+    fn create_request(path: &str, headers: &str) -> HttpMessage {
+        let raw = format!("GET {} HTTP/1.1\r\nHost: backend\r\n{}\r\n", path, headers);
+        read_request(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn interception_requires_every_header() {
+        let interception = create_interception("headers: {x-user: alice, x-debug: 'true'}");
+
+        assert!(interception.matches(&create_request("/", "X-User: alice\r\nX-Debug: true\r\n")));
+        assert!(!interception.matches(&create_request("/", "X-User: alice\r\n")));
+        assert!(!interception.matches(&create_request("/", "X-User: bob\r\nX-Debug: true\r\n")));
+    }
+
+    #[test]
+    fn interception_matches_path_prefixes() {
+        let interception = create_interception("paths: [/api/orders, /health]");
+
+        assert!(interception.matches(&create_request("/api/orders/42", "")));
+        assert!(interception.matches(&create_request("/health", "")));
+        assert!(!interception.matches(&create_request("/api/users", "")));
+    }
+
+    #[test]
+    fn interception_without_paths_matches_every_path() {
+        let interception = create_interception("headers: {x-user: alice}");
+
+        assert!(interception.matches(&create_request("/", "X-User: alice\r\n")));
+        assert!(interception.matches(&create_request("/api/orders?page=2", "X-User: alice\r\n")));
+        assert!(create_interception("{}").matches(&create_request("/anything", "")));
+    }
+
+    #[test]
+    fn reset_fault_resets_the_connection() {
+        let faults_file = env::temp_dir().join(format!("minitel-proxy-{}", process::id())).join("faults.json");
+        write_faults(&faults_file, &Faults { reset_rate: 1.0, ..Faults::default() });
+        let config = ProxyConfig { upstream: String::from("127.0.0.1:1"), faults_file: faults_file.clone(), interception: None, recording: None };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: backend\r\n\r\n").unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        assert!(handle_connection(server, &config).is_none());
+        assert_eq!(client.read(&mut [0; 16]).unwrap_err().kind(), ErrorKind::ConnectionReset);
+
+        fs::remove_dir_all(faults_file.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use crate::Definition;
+use crate::DeployMode;
+use crate::GlobalConfig;
+use crate::ServiceConfig;
+use crate::k8s::create_service_name;
+use crate::k8s::create_cluster_address;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use minijinja::Environment;
+use minijinja::UndefinedBehavior;
+use serde_json::json;
+use serde_json::Value;
+
+/// Variables available to the extra manifests of `.minitel/kube`.
+pub fn build_template_context(global_config: &GlobalConfig, def: &Definition, cfg: &HashMap<String, ServiceConfig>) -> Value {
+    let services: serde_json::Map<String, Value> = def.services.iter().map(|service| {
+        let deploy = cfg.get(&service.name).map(|s| &s.deploy).unwrap_or(&DeployMode::Cluster);
+        (service.name.clone(), json!({
+            "name": service.name,
+            "port": service.port,
+            "deploy": deploy,
+            "service_name": create_service_name(&service.name),
+            "url": create_cluster_address(service, deploy).url()
+        }))
+    }).collect();
+
+    json!({
+        "app_name": def.app_name,
+        "hostname": def.hostname,
+        "namespace": global_config.kube.namespace,
+        "host_ip": global_config.host_ip,
+        "minikube_ip": global_config.minikube_ip,
+        "services": services
+    })
+}
+
+fn is_defined(context: &Value, variable: &str) -> bool {
+    variable.split('.')
+        .try_fold(context, |value, key| value.get(key))
+        .is_some()
+}
+
+/// Renders a template, failing with the list of every undefined variable it uses.
+pub fn render_template(name: &str, source: &str, context: &Value) -> Result<String, String> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+
+    let template = env.template_from_named_str(name, source).map_err(|e| e.to_string())?;
+
+    let globals: HashSet<&str> = env.globals().map(|(name, _)| name).collect();
+    let mut undefined: Vec<String> = template.undeclared_variables(true).into_iter()
+        .filter(|v| !globals.contains(v.split('.').next().unwrap_or_default()))
+        .filter(|v| !is_defined(context, v))
+        .collect();
+
+    if !undefined.is_empty() {
+        undefined.sort();
+        return Err(format!("undefined variables: {}", undefined.join(", ")));
+    }
+
+    template.render(context).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::MemoryCluster;
+    use crate::KubeTarget;
+
+    fn render(source: &str) -> Result<String, String> {
+        let def: Definition = serde_yaml::from_str("
+            hostname: shop.test
+            app-name: shop
+            services:
+              - name: frontend
+                port: 9000
+              - name: backend
+                port: 8000
+        ").unwrap();
+        let cfg: HashMap<String, ServiceConfig> = serde_yaml::from_str("
+            backend:
+              deploy: local
+        ").unwrap();
+        let kube = KubeTarget { context: "minikube".to_string(), namespace: "shop".to_string() };
+        let cluster = MemoryCluster::default();
+        let global_config = GlobalConfig {
+            host_ip: "192.168.49.1",
+            minikube_ip: "192.168.49.2",
+            app_name: "shop",
+            hostname: "shop.test",
+            config_hash: "1234",
+            project_location: ".",
+            kube: &kube,
+            cluster: &cluster
+        };
+
+        render_template("test.yaml", source, &build_template_context(&global_config, &def, &cfg))
+    }
+
+    #[test]
+    fn renders_service_urls_and_host_ip() {
+        assert_eq!(render("{{ services.frontend.url }} {{ services.backend.url }} {{ host_ip }}").unwrap(), "http://frontend-service http://host.minikube.internal:8000 192.168.49.1");
+    }
+
+    #[test]
+    fn renders_conditionals_on_deploy_mode() {
+        let source = "{% for name in ['frontend', 'backend'] %}{{ name }}: {% if services[name].deploy == 'local' %}host{% else %}cluster{% endif %}\n{% endfor %}";
+
+        assert_eq!(render(source).unwrap(), "frontend: cluster\nbackend: host\n");
+    }
+
+    #[test]
+    fn fails_on_undefined_variables() {
+        assert_eq!(render("{{ services.payments.url }} {{ app_name }} {{ registry }}"), Err(String::from("undefined variables: registry, services.payments.url")));
+    }
+}
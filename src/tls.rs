@@ -0,0 +1,86 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str;
+
+/// Directory of the development CA. It is shared by every project so that it only needs to be trusted once.
+fn get_ca_dir() -> PathBuf {
+    let home = env::var("HOME").expect("Could not find home directory");
+    Path::new(&home).join(".minitel/ca")
+}
+
+pub fn get_ca_cert_path() -> PathBuf {
+    get_ca_dir().join("ca.crt")
+}
+
+fn run_openssl(dir: &Path, args: &[&str]) {
+    let output = Command::new("openssl")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("Failed to execute openssl. Is it installed?");
+
+    if !output.status.success() {
+        panic!("openssl {} failed: {}", args[0], str::from_utf8(&output.stderr).unwrap().trim());
+    }
+}
+
+/// Creates the development CA, unless it already exists.
+pub fn ensure_ca() {
+    let dir = get_ca_dir();
+    if dir.join("ca.crt").is_file() && dir.join("ca.key").is_file() {
+        return;
+    }
+
+    fs::create_dir_all(&dir).expect("Could not create CA directory");
+    run_openssl(&dir, &[
+        "req", "-x509", "-newkey", "rsa:2048", "-nodes",
+        "-keyout", "ca.key", "-out", "ca.crt", "-days", "3650",
+        "-subj", "/CN=minitel development CA",
+        "-addext", "basicConstraints=critical,CA:TRUE",
+        "-addext", "keyUsage=critical,keyCertSign,cRLSign"
+    ]);
+    println!("Created development CA {}", dir.join("ca.crt").display());
+}
+
+/// Certificate and key, in PEM, for the given hostnames, signed by the development CA.
+///
+/// They are kept in `.minitel/tls` and only issued again when the hostnames change.
+pub fn ensure_certificate(project_location: &str, hostnames: &[String]) -> (String, String) {
+    ensure_ca();
+
+    let dir = Path::new(project_location).join(".minitel/tls");
+    let hosts = hostnames.join(",");
+    let issued = fs::read_to_string(dir.join("hosts")).is_ok_and(|h| h == hosts)
+        && dir.join("cert.pem").is_file()
+        && dir.join("key.pem").is_file();
+
+    if !issued {
+        fs::create_dir_all(&dir).expect("Could not create TLS directory");
+        let alt_names: Vec<String> = hostnames.iter().map(|h| format!("DNS:{}", h)).collect();
+        fs::write(dir.join("cert.ext"), format!("subjectAltName={}\nextendedKeyUsage=serverAuth\n", alt_names.join(",")))
+            .expect("Could not write certificate extensions");
+
+        let ca_dir = get_ca_dir();
+        run_openssl(&dir, &[
+            "req", "-new", "-newkey", "rsa:2048", "-nodes",
+            "-keyout", "key.pem", "-out", "cert.csr",
+            "-subj", &format!("/CN={}", hostnames[0])
+        ]);
+        run_openssl(&dir, &[
+            "x509", "-req", "-in", "cert.csr", "-out", "cert.pem", "-days", "825", "-sha256",
+            "-CA", ca_dir.join("ca.crt").to_str().unwrap(),
+            "-CAkey", ca_dir.join("ca.key").to_str().unwrap(),
+            "-CAcreateserial", "-extfile", "cert.ext"
+        ]);
+        fs::write(dir.join("hosts"), &hosts).expect("Could not write certificate hosts");
+        println!("Issued certificate for {}", hosts);
+    }
+
+    (
+        fs::read_to_string(dir.join("cert.pem")).expect("Could not read certificate"),
+        fs::read_to_string(dir.join("key.pem")).expect("Could not read certificate key")
+    )
+}
@@ -0,0 +1,80 @@
+use crate::background;
+use crate::k8s::KubeTarget;
+use crate::k8s::create_service_name;
+
+use std::fs;
+use std::net::TcpListener;
+
+fn read_tunnel_ports(project_location: &str, service_name: &str) -> Option<Vec<u16>> {
+    fs::read_to_string(background::get_run_file(project_location, service_name, "tunnel", "port")).ok()?
+        .split_whitespace()
+        .map(|port| port.parse().ok())
+        .collect()
+}
+
+/// Local ports a cluster service is forwarded to, in the order they were requested, if its tunnel is running.
+pub fn find_tunnel_ports(project_location: &str, service_name: &str) -> Option<Vec<u16>> {
+    if !background::is_running(project_location, service_name, "tunnel") {
+        return None;
+    }
+    read_tunnel_ports(project_location, service_name)
+}
+
+/// Picks a free local port, `preferred` if it still is.
+///
+/// The listener is returned so that the port stays reserved while others are picked.
+pub fn allocate_port(preferred: Option<u16>) -> (u16, TcpListener) {
+    let listener = preferred.and_then(|port| TcpListener::bind(("127.0.0.1", port)).ok())
+        .or_else(|| TcpListener::bind(("127.0.0.1", 0)).ok())
+        .expect("Could not find a free local port");
+
+    (listener.local_addr().expect("Could not find a free local port").port(), listener)
+}
+
+/// Starts a background port-forward from local ports to the given ports of the Service of a
+/// cluster service, and returns the local ports. Those of the previous tunnel are reused when possible.
+///
+/// kubectl stops forwarding when the pod behind the Service goes away, so it runs in a loop
+/// which reconnects to the pods replacing it.
+pub fn start_tunnel(project_location: &str, kube: &KubeTarget, service_name: &str, remote_ports: &[i32]) -> Vec<u16> {
+    start_tunnel_to(project_location, kube, service_name, &format!("service/{}", create_service_name(service_name)), remote_ports)
+}
+
+/// Same as `start_tunnel`, to `target` (such as `service/<name>` or `deployment/<name>`) rather
+/// than the Service fronting the service.
+pub fn start_tunnel_to(project_location: &str, kube: &KubeTarget, service_name: &str, target: &str, remote_ports: &[i32]) -> Vec<u16> {
+    let previous_ports = read_tunnel_ports(project_location, service_name).unwrap_or_default();
+    stop_tunnel(project_location, service_name);
+
+    let allocated: Vec<(u16, TcpListener)> = (0..remote_ports.len())
+        .map(|i| allocate_port(previous_ports.get(i).cloned()))
+        .collect();
+    let ports: Vec<u16> = allocated.into_iter().map(|(port, _)| port).collect();
+
+    let script = "( trap 'kill $child 2> /dev/null; exit' TERM; \
+                  while :; do \
+                    kubectl --context \"$0\" port-forward \"$@\" & child=$!; \
+                    wait $child; \
+                    sleep 1; \
+                  done )";
+    let mut args = vec!(kube.context.clone(), String::from("--namespace"), kube.namespace.clone(), target.to_string());
+    args.extend(ports.iter().zip(remote_ports).map(|(local, remote)| format!("{}:{}", local, remote)));
+
+    if !background::spawn(project_location, service_name, "tunnel", script, args, None) {
+        panic!("Could not start tunnel to {}", service_name);
+    }
+
+    let port_list: Vec<String> = ports.iter().map(|port| port.to_string()).collect();
+    fs::write(background::get_run_file(project_location, service_name, "tunnel", "port"), port_list.join(" ")).expect("Could not write port file");
+
+    ports
+}
+
+/// Stops the tunnel of a service. Its ports are remembered for the next one.
+pub fn stop_tunnel(project_location: &str, service_name: &str) {
+    background::stop(project_location, service_name, "tunnel");
+}
+
+pub fn stop_all_tunnels(project_location: &str) {
+    background::stop_all(project_location, "tunnel");
+}
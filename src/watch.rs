@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use glob::Pattern;
+
+/// Files which are always ignored. `.pid` is written by the start scripts themselves.
+const DEFAULT_IGNORES: [&str; 4] = [".git", ".pid", "node_modules", "*.swp"];
+
+/// Detects changes in a directory tree by comparing modification times between polls.
+pub struct Watcher {
+    root: PathBuf,
+    ignore: Vec<Pattern>,
+    snapshot: HashMap<PathBuf, SystemTime>
+}
+
+impl Watcher {
+    pub fn new(root: &Path, ignore: &[String]) -> Watcher {
+        let ignore = DEFAULT_IGNORES.iter().map(|p| p.to_string())
+            .chain(ignore.iter().cloned())
+            .map(|p| Pattern::new(&p).unwrap_or_else(|e| panic!("Invalid ignore pattern {}: {}", p, e)))
+            .collect();
+
+        let mut watcher = Watcher {
+            root: root.to_path_buf(),
+            ignore,
+            snapshot: HashMap::new()
+        };
+        watcher.snapshot = watcher.scan();
+        watcher
+    }
+
+    /// Returns true if anything was added, removed or modified since the last poll.
+    pub fn poll(&mut self) -> bool {
+        let snapshot = self.scan();
+        let changed = snapshot != self.snapshot;
+        self.snapshot = snapshot;
+        changed
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+
+        self.ignore.iter().any(|p| p.matches_path(relative) || p.matches(file_name))
+    }
+
+    fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        let mut acc = HashMap::new();
+        let mut pending = vec!(self.root.clone());
+
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue
+            };
+            for entry in entries.map_while(Result::ok) {
+                let path = entry.path();
+                if self.is_ignored(&path) {
+                    continue;
+                }
+                match entry.metadata() {
+                    Ok(meta) if meta.is_dir() => pending.push(path),
+                    Ok(meta) => {
+                        acc.insert(path, meta.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+                    },
+                    Err(_) => {}
+                }
+            }
+        }
+
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn is_ignored_matches_defaults_relative_paths_and_file_names() {
+        let root = Path::new("/project/backend");
+        let watcher = Watcher::new(root, &["dist/*".to_string(), "*.log".to_string()]);
+
+        assert!(watcher.is_ignored(&root.join(".git")));
+        assert!(watcher.is_ignored(&root.join(".pid")));
+        assert!(watcher.is_ignored(&root.join("web/node_modules")));
+        assert!(watcher.is_ignored(&root.join("src/.main.rs.swp")));
+        assert!(watcher.is_ignored(&root.join("dist/app.js")));
+        assert!(watcher.is_ignored(&root.join("logs/server.log")));
+        assert!(!watcher.is_ignored(&root.join("src/main.rs")));
+        assert!(!watcher.is_ignored(&root.join("src/dist/app.js")));
+    }
+
+    #[test]
+    fn poll_skips_changes_to_ignored_files() {
+        let root = env::temp_dir().join(format!("minitel-watch-{}", process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+
+        let mut watcher = Watcher::new(&root, &["*.log".to_string()]);
+        assert!(!watcher.poll());
+
+        fs::write(root.join("server.log"), "started").unwrap();
+        fs::write(root.join(".pid"), "42").unwrap();
+        assert!(!watcher.poll());
+
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        fs::remove_file(root.join("src/lib.rs")).unwrap();
+        assert!(watcher.poll());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}